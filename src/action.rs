@@ -1,36 +1,64 @@
-use std::io::Read;
-use std::mem::transmute;
+use std::collections::HashMap;
+use std::io::{self, Read, Write, ErrorKind};
+use std::mem::{self, transmute};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use space::*;
 use space::Direction::*;
 use area::*;
 use entity::*;
 use pushable::*;
 use notification::*;
+use appearance::*;
 use cooldown::*;
+use event::*;
+use protocol::*;
+use text::*;
+use json::*;
+use websocket::*;
+use handshake::*;
 use cooldown::CooldownState::*;
 
 
-#[derive(PartialEq, Debug)]
-enum Command {
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
     LookAt(Offset),
     Move(Direction),
     Drill(Direction),
+    Wait(u8),
+    Scan(u8),
+    // Sent something that isn't a command
     Malformed,
+    // Sent a framed command that isn't one this server knows, which can be skipped
+    Unknown,
+    // Hung up, even if part way through a command
     End,
+    // Didn't send a whole command in time
+    TimedOut,
 }
 
 
 const CODE_LOOK_AT: u8 = 1;
 const CODE_MOVE: u8 = 2;
 const CODE_DRILL: u8 = 3;
+const CODE_WAIT: u8 = 4;
+const CODE_SCAN: u8 = 5;
 
 const CODE_NORTH: u8 = 0;
 const CODE_EAST: u8 = 1;
 const CODE_SOUTH: u8 = 2;
 const CODE_WEST: u8 = 3;
 
+// Keeps a scan's reply under a kilobyte and its cost within a cooldown.
+const MAX_SCAN_RADIUS: u8 = 15;
+
+// How often bots whose commands are on their way are checked on while waiting for them.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// The least time a tick waits for the commands of bots with a timeout, which are the ones playing
+// over the network, so that they get a chance to answer even when the turn delay is 0.
+pub const MIN_NETWORK_WAIT: Duration = Duration::from_millis(50);
+
 
 fn code_to_direction(code: u8) -> Option<Direction> {
     match code {
@@ -43,6 +71,12 @@ fn code_to_direction(code: u8) -> Option<Direction> {
 }
 
 
+// Seeing a bigger area takes longer, but less long than looking at each square in turn would.
+fn scan_ticks(radius: u8) -> u8 {
+    1 + radius * radius
+}
+
+
 fn i8_into_u8(x: i8) -> u8 {
     unsafe { transmute(x) }
 }
@@ -51,85 +85,265 @@ fn i8_from_u8(x: u8) -> i8 {
 }
 
 
-fn parse_next(bytes: &mut Read) -> Command {
-    let mut buf = [0];
+// How many bytes the command with this code takes up, code included.
+fn command_length(code: u8) -> Option<usize> {
+    match code {
+        CODE_LOOK_AT => Some(3),
+        CODE_MOVE | CODE_DRILL | CODE_WAIT | CODE_SCAN => Some(2),
+        _ => None,
+    }
+}
+
+fn decode_command(bytes: &[u8]) -> Command {
+    match bytes[0] {
+        CODE_LOOK_AT => {
+            // positive Y is north, positive X is east
+            let dx = i8_from_u8(bytes[1]) as i32;
+            let dy = i8_from_u8(bytes[2]) as i32;
+            Command::LookAt(East * dx + North * dy)
+        }
+        CODE_MOVE => code_to_direction(bytes[1]).map_or(Command::Malformed, Command::Move),
+        CODE_DRILL => code_to_direction(bytes[1]).map_or(Command::Malformed, Command::Drill),
+        CODE_WAIT => match bytes[1] {
+            0 => Command::Malformed,
+            ticks => Command::Wait(ticks),
+        },
+        CODE_SCAN => match bytes[1] {
+            radius if radius <= MAX_SCAN_RADIUS => Command::Scan(radius),
+            _ => Command::Malformed,
+        },
+        _ => Command::Malformed,
+    }
+}
+
+
+// A command on its way from a bot, put back together however its bytes are split up on the way.
+// Never reads past the end of the command, so whatever the bot sent after it is left for its next
+// turn.
+pub struct CommandReader {
+    // The bot is given up on if its command isn't all there by then.
+    deadline: Option<Instant>,
+    protocol: Protocol,
+    // Everything read so far
+    bytes: Vec<u8>,
+}
+impl CommandReader {
+    pub fn new(deadline: Option<Instant>, protocol: Protocol) -> CommandReader {
+        CommandReader {
+            deadline: deadline,
+            protocol: protocol,
+            bytes: Vec::with_capacity(3),
+        }
+    }
     
-    // TODO: read_exact
-    if bytes.read(&mut buf).ok() != Some(1) {
-        return Command::End;
+    // Reads whatever has arrived without waiting for more. Returns the command along with the
+    // bytes it was sent as once it's all there, or None while it's still on its way.
+    pub fn poll(&mut self, input: &mut Read) -> Option<(Command, Vec<u8>)> {
+        let command = match self.parse(input) {
+            Ok(command) => command,
+            Err(None) => return None,
+            Err(Some(command)) => command,
+        };
+        Some((command, mem::replace(&mut self.bytes, vec![])))
     }
     
-    match buf[0] {
-        CODE_LOOK_AT => {
-            if bytes.read(&mut buf).ok() != Some(1) {
-                return Command::End;
+    fn parse(&mut self, input: &mut Read) -> Result<Command, Option<Command>> {
+        match self.protocol {
+            Protocol::Binary => {
+                try!(self.fill(input, 1));
+                let length = match command_length(self.bytes[0]) {
+                    None => return Ok(Command::Malformed),
+                    Some(length) => length,
+                };
+                try!(self.fill(input, length));
+                Ok(decode_command(&self.bytes))
             }
-            
-            let dx = i8_from_u8(buf[0]) as i32;
-            
-            if bytes.read(&mut buf).ok() != Some(1) {
-                return Command::End;
+            Protocol::Framed => {
+                try!(self.fill(input, 2));
+                let length = (self.bytes[0] as usize) << 8 | self.bytes[1] as usize;
+                try!(self.fill(input, 2 + length));
+                
+                // the whole frame has been read, so anything wrong with it can be skipped
+                let message = &self.bytes[2..];
+                let command = match message.first().and_then(|code| command_length(*code)) {
+                    Some(length) if length == message.len() => decode_command(message),
+                    _ => Command::Unknown,
+                };
+                Ok(match command {
+                    Command::Malformed => Command::Unknown,
+                    command => command,
+                })
             }
-            
-            let dy = i8_from_u8(buf[0]) as i32;
-            
-            ///////////// Positive Y is north, positive X is east
-            
-            return Command::LookAt(East * dx + North * dy);
-        },
-        CODE_MOVE => {
-            if bytes.read(&mut buf).ok() != Some(1) {
-                return Command::End;
+            Protocol::Text | Protocol::Json => {
+                try!(self.fill_line(input));
+                let message = match self.protocol {
+                    Protocol::Json => json_command_message(&self.bytes),
+                    _ => command_message(&self.bytes),
+                };
+                let command = message.map_or(Command::Unknown, |message| decode_command(&message));
+                Ok(match command {
+                    Command::Malformed => Command::Unknown,
+                    command => command,
+                })
             }
-            
-            return match code_to_direction(buf[0]) {
-                Some(direction) => Command::Move(direction),
-                None => Command::Malformed,
-            };
-        },
-        CODE_DRILL => {
-            if bytes.read(&mut buf).ok() != Some(1) {
-                return Command::End;
+        }
+    }
+    
+    fn fill_line(&mut self, input: &mut Read) -> Result<(), Option<Command>> {
+        let max_length = match self.protocol {
+            // a whole WebSocket message and the newline it's passed on with
+            Protocol::Json => MAX_MESSAGE_LENGTH + 1,
+            _ => MAX_LINE_LENGTH,
+        };
+        match continue_line(input, &mut self.bytes, max_length) {
+            Ok(()) => Ok(()),
+            // too long to be a command
+            Err(ref error) if error.kind() == ErrorKind::InvalidData => {
+                Err(Some(Command::Malformed))
             }
-            
-            return match code_to_direction(buf[0]) {
-                Some(direction) => Command::Drill(direction),
-                None => Command::Malformed,
-            };
-        },
-        _ => return Command::Malformed,
+            Err(error) => Err(self.failure(error)),
+        }
+    }
+    
+    // Reads until there are this many bytes. Fails with None if the rest hasn't arrived yet, or
+    // with the command to give up with.
+    fn fill(&mut self, input: &mut Read, length: usize) -> Result<(), Option<Command>> {
+        let mut buf = [0; 64];
+        while self.bytes.len() < length {
+            let wanted = (length - self.bytes.len()).min(buf.len());
+            match input.read(&mut buf[..wanted]) {
+                Ok(0) => return Err(Some(Command::End)),
+                Ok(count) => self.bytes.extend_from_slice(&buf[..count]),
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(self.failure(error)),
+            }
+        }
+        Ok(())
+    }
+    
+    // None if the read only gave up waiting for the rest, or the command to give up with.
+    fn failure(&self, error: io::Error) -> Option<Command> {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => match self.deadline {
+                Some(deadline) if Instant::now() >= deadline => Some(Command::TimedOut),
+                _ => None,
+            },
+            _ => Some(Command::End),
+        }
+    }
+}
+
+
+// Reads the next command and the bytes it was sent as, waiting for it to arrive. TimedOut if it
+// isn't all there by the deadline.
+pub fn parse_next(bytes: &mut Read, deadline: Option<Instant>, protocol: Protocol)
+        -> (Command, Vec<u8>) {
+    let mut reader = CommandReader::new(deadline, protocol);
+    loop {
+        if let Some(result) = reader.poll(bytes) {
+            return result;
+        }
+        thread::sleep(POLL_INTERVAL);
     }
 }
 
 
 impl Area {
     pub fn notify(&mut self, bot: Entity, notification: Notification) {
-        let error = {
-            let mut output = match self.outputs.of_mut_ref(bot) {
-                None => return,
-                Some(output) => output,
-            };
-            match notify(&mut output, notification) {
-                Ok(_) => return,
-                Err(error) => error,
-            }
+        let protocol = self.protocols.of(bot).unwrap_or(Protocol::Binary);
+        let bytes = serialize_notification(notification, protocol);
+        let result = match self.outputs.of_mut_ref(bot) {
+            None => return,
+            Some(output) => output.write_all(&bytes),
         };
-        println!("{:?} disconnected during notify: {:?}", bot, error);
-        self.disconnect(bot);
+        match result {
+            Ok(()) => self.record_received(bot, &bytes),
+            Err(error) => {
+                let name = self.names.of(bot).unwrap_or_else(|| format!("{:?}", bot));
+                println!("{} disconnected during notify: {:?}", name, error);
+                self.drop_connection(bot, Disconnection::Closed);
+            }
+        }
     }
     
-    fn act(&mut self, bot: Entity) {
+    // Gives a single bot its turn, if it's ready for one and its command has arrived.
+    pub fn act(&mut self, bot: Entity) {
+        for (bot, command) in self.gather(&[bot], Instant::now()) {
+            self.perform(bot, command);
+        }
+    }
+    
+    // Tells the bot it's its turn unless it's still busy. Returns whether a command is expected
+    // from it, including one it was asked for on an earlier tick that hasn't arrived yet.
+    fn prompt(&mut self, bot: Entity) -> bool {
+        if self.turns.attached(bot) {
+            return true;
+        }
         if self.tick(bot) == Waiting {
-            return;
+            return false;
         }
         
         self.notify(bot, Notification::YourTurn);
-        
-        let command = match self.inputs.of_mut_ref(bot) {
-            None => return,
-            Some(mut input) => parse_next(&mut input),
+        if !self.inputs.attached(bot) {
+            return false; // disconnected
+        }
+        // measured from when the bot was told it's its turn
+        let deadline = self.timeouts.of(bot).map(|timeout| Instant::now() + timeout);
+        let protocol = self.protocols.of(bot).unwrap_or(Protocol::Binary);
+        self.turns.attach(bot, CommandReader::new(deadline, protocol));
+        true
+    }
+    
+    fn poll(&mut self, bot: Entity) -> Option<Command> {
+        let result = match (self.inputs.of_mut_ref(bot), self.turns.of_mut_ref(bot)) {
+            (Some(input), Some(reader)) => reader.poll(input),
+            _ => return None,
+        };
+        result.map(|(command, sent)| {
+            self.turns.detach(bot);
+            self.record_sent(bot, &sent);
+            command
+        })
+    }
+    
+    // Tells every bot that's ready that it's its turn, then collects the commands that arrive
+    // before the given time, in turn order. Bots whose commands are still on their way keep their
+    // turn and are polled again on the next tick, so a slow bot only holds up itself. Inputs that
+    // block are waited on, so bots running in the same process always make it.
+    pub fn gather(&mut self, entities: &[Entity], until: Instant) -> Vec<(Entity, Command)> {
+        let expected = entities.iter().cloned().filter(|bot| self.prompt(*bot))
+                               .collect::<Vec<_>>();
+        let until = if expected.iter().any(|bot| self.timeouts.attached(*bot)) {
+            until.max(Instant::now() + MIN_NETWORK_WAIT)
+        }
+        else {
+            until
         };
+        let mut commands = HashMap::new();
+        loop {
+            for bot in &expected {
+                if !commands.contains_key(bot) {
+                    if let Some(command) = self.poll(*bot) {
+                        commands.insert(*bot, command);
+                    }
+                }
+            }
+            if commands.len() == expected.len() || Instant::now() >= until {
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
         
+        for bot in &expected {
+            if !commands.contains_key(bot) {
+                self.record_late(*bot);
+            }
+        }
+        expected.into_iter().filter_map(|bot| commands.remove(&bot).map(|command| (bot, command)))
+                .collect()
+    }
+    
+    pub fn perform(&mut self, bot: Entity, command: Command) {
         match command {
             Command::LookAt(offset) => {
                 let here = match self.positions.of(bot) {
@@ -139,7 +353,8 @@ impl Area {
                     }
                     Some(here) => here,
                 };
-                let notification = Notification::YouSee(self.appearance_at(here + offset));
+                let notification = Notification::YouSee(self.appearance_for(bot, here,
+                                                                            here + offset));
                 self.notify(bot, notification);
             }
             Command::Move(direction) => {
@@ -159,10 +374,62 @@ impl Area {
                     Some(DrillResult::DestroysEnterer) => (), // notified in remove() function
                 };
             }
-            Command::Malformed | Command::End => self.disconnect(bot),
+            Command::Scan(..) if !self.capable(bot, CAPABILITY_SCAN) => {
+                // as if the server didn't know the command
+                let command = match self.protocols.of(bot).unwrap_or(Protocol::Binary) {
+                    Protocol::Binary => Command::Malformed,
+                    _ => Command::Unknown,
+                };
+                self.perform(bot, command);
+            }
+            Command::Scan(radius) => {
+                let here = match self.positions.of(bot) {
+                    None => {
+                        debug_unreachable!();
+                        return;
+                    }
+                    Some(here) => here,
+                };
+                self.acted(bot, scan_ticks(radius));
+                let notification = Notification::YouScan {
+                    radius: radius,
+                    appearances: self.scan(bot, here, radius as i32),
+                };
+                self.notify(bot, notification);
+            }
+            Command::Wait(ticks) => {
+                self.acted(bot, ticks);
+                self.notify(bot, Notification::Success);
+            }
+            // the bot keeps its turn and is asked again on the next tick
+            Command::Unknown => self.notify(bot, Notification::CommandError),
+            Command::Malformed => self.drop_connection(bot, Disconnection::Malformed),
+            Command::End => self.drop_connection(bot, Disconnection::Closed),
+            Command::TimedOut => self.drop_connection(bot, Disconnection::TimedOut),
         }
     }
     
+    // Rows from north to south, each from west to east.
+    fn scan(&self, bot: Entity, center: Position, radius: i32) -> Vec<Appearance> {
+        let mut result = Vec::with_capacity(((radius * 2 + 1) * (radius * 2 + 1)) as usize);
+        for dy in (-radius..radius + 1).rev() {
+            for dx in -radius..radius + 1 {
+                result.push(self.appearance_for(bot, center, center + East * dx + North * dy));
+            }
+        }
+        result
+    }
+    
+    fn drop_connection(&mut self, bot: Entity, reason: Disconnection) {
+        self.emit(Event::Disconnected {
+            entity: bot,
+            reason: reason,
+        });
+        let name = self.names.of(bot).unwrap_or(String::new());
+        self.disconnected.push(name);
+        self.disconnect(bot);
+    }
+    
     pub fn all_actors(&self) -> Vec<Entity> {
         let mut result = Vec::with_capacity(self.inputs.contents.len());
         for (entity, _) in &self.inputs.contents {
@@ -172,23 +439,51 @@ impl Area {
         result
     }
     
-    fn act_vec(&mut self, entities: Vec<Entity>) {
-        for entity in entities {
-            self.act(entity);
+    // Returns the winners of the round. Each tick lasts the delay, and bots have until its end to
+    // send their commands.
+    pub fn act_all(&mut self, delay: Duration) -> Vec<Entity> {
+        while !self.round_over() {
+            let end = Instant::now() + delay;
+            self.act_tick_until(end);
+            
+            let now = Instant::now();
+            if now < end {
+                thread::sleep(end - now);
+            }
         }
+        self.all_actors()
     }
     
-    // Returns the winners of the round
-    pub fn act_all(&mut self, delay: Duration) -> Vec<Entity> {
-        loop {
-            let entities = self.all_actors();
-            if entities.len() <= 1 {
-                return entities;
+    pub fn round_over(&self) -> bool {
+        self.teams_remaining() <= 1
+            || self.rules.tick_limit.map_or(false, |limit| self.ticks >= limit)
+    }
+    
+    // Acts on whichever commands have already arrived.
+    pub fn act_tick(&mut self) {
+        self.act_tick_until(Instant::now());
+    }
+    
+    pub fn act_tick_until(&mut self, until: Instant) {
+        let entities = self.turn_order();
+        let commands = self.gather(&entities, until);
+        // counted apart from ticks so that bots on cooldown can't keep the rotation in step
+        if !commands.is_empty() {
+            self.acting_ticks += 1;
+        }
+        if self.rules.simultaneous {
+            self.act_simultaneously(commands);
+        }
+        else {
+            for (bot, command) in commands {
+                // unless something got to it first
+                if self.inputs.attached(bot) {
+                    self.perform(bot, command);
+                }
             }
-            self.act_vec(entities);
-            
-            thread::sleep(delay);
         }
+        self.run_conveyors();
+        self.ticks += 1;
     }
 }
 
@@ -199,69 +494,219 @@ mod tests {
                 CODE_NORTH, CODE_EAST, CODE_WEST};
     use space::*;
     use space::Direction::*;
-    use std::io::Cursor;
+    use std::io::{self, Read, Cursor, ErrorKind};
     use std::rc::Rc;
     use std::cell::RefCell;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use area::*;
+    use event::*;
     use notification::*;
+    use protocol::*;
+    use handshake::*;
     
     use super::super::tests::{SharedWrite};
     
+    // Hands over one byte at a time and makes the reader wait before each one.
+    struct Trickle {
+        bytes: Vec<u8>,
+        ready: bool,
+    }
+    impl Trickle {
+        fn new(bytes: Vec<u8>) -> Trickle {
+            Trickle {
+                bytes: bytes,
+                ready: true,
+            }
+        }
+    }
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.ready = !self.ready;
+            if !self.ready {
+                return Err(io::Error::new(ErrorKind::WouldBlock, "Not yet."));
+            }
+            if self.bytes.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.bytes.remove(0);
+            Ok(1)
+        }
+    }
+    
     #[test]
     fn parsing() {
         let mut commands = Cursor::new([]);
-        assert_eq!(parse_next(&mut commands), Command::End);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::End);
         
         let mut commands = Cursor::new([
             1, i8_into_u8(0i8), i8_into_u8(0i8)
         ]);
-        assert_eq!(parse_next(&mut commands), Command::LookAt(Offset::default()));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0,
+                   Command::LookAt(Offset::default()));
         
         let mut commands = Cursor::new([
             1, i8_into_u8(1i8), i8_into_u8(0i8)
         ]);
-        assert_eq!(parse_next(&mut commands), Command::LookAt(Offset::default() + East));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0,
+                   Command::LookAt(Offset::default() + East));
         
         let mut commands = Cursor::new([
             1, i8_into_u8(-1i8), i8_into_u8(1i8)
         ]);
-        assert_eq!(parse_next(&mut commands), Command::LookAt(Offset::default() + West + North));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0,
+                   Command::LookAt(Offset::default() + West + North));
         
         let mut commands = Cursor::new([
             1, i8_into_u8(-2i8), i8_into_u8(1i8)
         ]);
-        assert_eq!(parse_next(&mut commands), Command::LookAt(Offset::default() + West * 2 + North));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0,
+                   Command::LookAt(Offset::default() + West * 2 + North));
         
         let mut commands = Cursor::new([2, 0]);
-        assert_eq!(parse_next(&mut commands), Command::Move(North));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Move(North));
         
         let mut commands = Cursor::new([2, 1]);
-        assert_eq!(parse_next(&mut commands), Command::Move(East));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Move(East));
         
         let mut commands = Cursor::new([2, 2]);
-        assert_eq!(parse_next(&mut commands), Command::Move(South));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Move(South));
         
         let mut commands = Cursor::new([2, 3]);
-        assert_eq!(parse_next(&mut commands), Command::Move(West));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Move(West));
         
         let mut commands = Cursor::new([2, 4]);
-        assert_eq!(parse_next(&mut commands), Command::Malformed);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Malformed);
         
         let mut commands = Cursor::new([3, 0]);
-        assert_eq!(parse_next(&mut commands), Command::Drill(North));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Drill(North));
         
         let mut commands = Cursor::new([3, 1]);
-        assert_eq!(parse_next(&mut commands), Command::Drill(East));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Drill(East));
         
         let mut commands = Cursor::new([3, 2]);
-        assert_eq!(parse_next(&mut commands), Command::Drill(South));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Drill(South));
         
         let mut commands = Cursor::new([3, 3]);
-        assert_eq!(parse_next(&mut commands), Command::Drill(West));
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Drill(West));
         
         let mut commands = Cursor::new([3, 4]);
-        assert_eq!(parse_next(&mut commands), Command::Malformed);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Malformed);
+        
+        let mut commands = Cursor::new([4, 1]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Wait(1));
+        
+        let mut commands = Cursor::new([4, 255]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Wait(255));
+        
+        let mut commands = Cursor::new([4, 0]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Malformed);
+        
+        let mut commands = Cursor::new([5, 0]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Scan(0));
+        
+        let mut commands = Cursor::new([5, 15]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Scan(15));
+        
+        let mut commands = Cursor::new([5, 16]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary).0, Command::Malformed);
+    }
+    
+    #[test]
+    fn framed_commands() {
+        let mut bytes = frame(&[2, 1]);
+        bytes.extend(frame(&[1, 255, 0]));
+        // unknown, with the wrong length, invalid and empty
+        bytes.extend(frame(&[20, 1, 2, 3]));
+        bytes.extend(frame(&[2, 1, 0]));
+        bytes.extend(frame(&[3, 9]));
+        bytes.extend(frame(&[]));
+        bytes.extend(frame(&[5, 2]));
+        bytes.extend_from_slice(&[0, 2, 2]);
+        let mut commands = Trickle::new(bytes);
+        
+        let mut next = || parse_next(&mut commands, None, Protocol::Framed);
+        assert_eq!(next(), (Command::Move(East), vec![0, 2, 2, 1]));
+        assert_eq!(next().0, Command::LookAt(Offset::default() + West));
+        assert_eq!(next(), (Command::Unknown, vec![0, 4, 20, 1, 2, 3]));
+        assert_eq!(next().0, Command::Unknown);
+        assert_eq!(next().0, Command::Unknown);
+        assert_eq!(next(), (Command::Unknown, vec![0, 0]));
+        assert_eq!(next().0, Command::Scan(2));
+        assert_eq!(next(), (Command::End, vec![0, 2, 2]));
+    }
+    
+    #[test]
+    fn text_commands() {
+        let lines = "look -1 1\r\nmove up\n\nwait 0\ndrill west\nscan 2".as_bytes().to_vec();
+        let mut commands = Trickle::new(lines);
+        
+        let mut next = || parse_next(&mut commands, None, Protocol::Text);
+        assert_eq!(next(), (Command::LookAt(Offset::default() + West + North),
+                            b"look -1 1\r\n".to_vec()));
+        assert_eq!(next().0, Command::Unknown);
+        assert_eq!(next(), (Command::Unknown, b"\n".to_vec()));
+        assert_eq!(next().0, Command::Unknown);
+        assert_eq!(next().0, Command::Drill(West));
+        // hung up before the end of the line
+        assert_eq!(next(), (Command::End, b"scan 2".to_vec()));
+        
+        let mut commands = Cursor::new(vec![b'a'; 300]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Text).0, Command::Malformed);
+    }
+    
+    #[test]
+    fn long_json_commands() {
+        let padding = "x".repeat(1000);
+        let message = format!("{{\"command\":\"move\",\"direction\":\"east\",\"note\":\"{}\"}}\n",
+                              padding);
+        let mut commands = Cursor::new(message.clone().into_bytes());
+        assert_eq!(parse_next(&mut commands, None, Protocol::Json),
+                   (Command::Move(East), message.into_bytes()));
+        
+        // longer than any WebSocket message
+        let mut commands = Cursor::new(vec![b' '; 70000]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Json).0, Command::Malformed);
+    }
+    
+    #[test]
+    fn fragmented_commands() {
+        let deadline = Some(Instant::now() + Duration::from_secs(60));
+        let mut commands = Trickle::new(vec![1, 255, 2, 2, 3, 4]);
+        assert_eq!(parse_next(&mut commands, deadline, Protocol::Binary),
+                   (Command::LookAt(Offset::default() + West + North * 2), vec![1, 255, 2]));
+        assert_eq!(parse_next(&mut commands, deadline, Protocol::Binary),
+                   (Command::Move(West), vec![2, 3]));
+        assert_eq!(parse_next(&mut commands, deadline, Protocol::Binary), (Command::End, vec![4]));
+    }
+    
+    #[test]
+    fn deadlines() {
+        let mut commands = Trickle::new(vec![2, 1]);
+        assert_eq!(parse_next(&mut commands, Some(Instant::now()), Protocol::Binary),
+                   (Command::TimedOut, vec![]));
+        
+        // giving up part way through
+        let mut commands = Trickle::new(vec![2, 1]);
+        commands.ready = false;
+        assert_eq!(parse_next(&mut commands, Some(Instant::now()), Protocol::Binary),
+                   (Command::TimedOut, vec![2]));
+        
+        // waiting as long as it takes
+        let mut commands = Trickle::new(vec![2, 1]);
+        assert_eq!(parse_next(&mut commands, None, Protocol::Binary),
+                   (Command::Move(East), vec![2, 1]));
+        
+        let mut area = Area::new();
+        let bot = make_bot(&mut area, Position::default());
+        area.inputs.attach(bot, Box::new(Trickle::new(vec![2, 1])));
+        area.outputs.attach(bot, Box::new(vec![]));
+        area.timeouts.attach(bot, Duration::from_millis(0));
+        area.act(bot);
+        assert_eq!(area.positions.of(bot), None);
+        assert_eq!(area.take_events()[0].event, Event::Disconnected {
+            entity: bot,
+            reason: Disconnection::TimedOut,
+        });
     }
     
     #[test]
@@ -327,6 +772,9 @@ mod tests {
             (vec![1, i8_into_u8(0), i8_into_u8(0)], vec![1, 6, 1]), // bot
             (vec![1, i8_into_u8(-1), i8_into_u8(0)], vec![1, 6, 2]), // wall
             (vec![1, i8_into_u8(0), i8_into_u8(1)], vec![1, 6, 3]), // abyss
+            (vec![4, 7], vec![1, 3]), // waited
+            (vec![5, 0], vec![1, 10, 0, 1]), // scanned self
+            (vec![5, 1], vec![1, 10, 1, 0, 3, 0, 2, 1, 0, 0, 0, 0]), // scanned neighbourhood
         ];
         
         for (instream, target) in streams {
@@ -407,4 +855,68 @@ mod tests {
             }
         }
     }
+    
+    #[test]
+    fn cooldown_after_waiting() {
+        let mut area = Area::new();
+        
+        let position = Position::default();
+        let bot = make_bot(&mut area, position);
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![
+            4, 7,
+            2, 1,
+        ])));
+        
+        for _ in 0..7 {
+            area.act(bot);
+            assert_eq!(area.positions.of(bot), Some(position));
+        }
+        area.act(bot);
+        assert_eq!(area.positions.of(bot), Some(position + East));
+    }
+    
+    #[test]
+    fn cooldown_after_scanning() {
+        let mut area = Area::new();
+        
+        let position = Position::default();
+        let bot = make_bot(&mut area, position);
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![
+            5, 2,
+            2, 1,
+        ])));
+        
+        for _ in 0..5 {
+            area.act(bot);
+            assert_eq!(area.positions.of(bot), Some(position));
+        }
+        area.act(bot);
+        assert_eq!(area.positions.of(bot), Some(position + East));
+    }
+    
+    #[test]
+    fn scanning_without_capability() {
+        let mut area = Area::new();
+        
+        let framed = make_bot(&mut area, Position::default());
+        let binary = make_bot(&mut area, Position::default() + East * 2);
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut commands = frame(&[5, 1]);
+        commands.extend(frame(&[4, 1]));
+        area.inputs.attach(framed, Box::new(Cursor::new(commands)));
+        area.outputs.attach(framed, Box::new(SharedWrite::new(output.clone())));
+        area.protocols.attach(framed, Protocol::Framed);
+        area.capabilities.attach(framed, CAPABILITY_KILLS);
+        area.inputs.attach(binary, Box::new(Cursor::new(vec![5, 1])));
+        area.outputs.attach(binary, Box::new(vec![]));
+        area.capabilities.attach(binary, 0);
+        
+        // YourTurn, CommandError, YourTurn, Success, each framed
+        area.act(framed);
+        area.act(framed);
+        assert_eq!(*output.borrow(), vec![0, 1, 1, 0, 1, 12, 0, 1, 1, 0, 1, 3]);
+        
+        area.act(binary);
+        assert!(!area.inputs.attached(binary));
+    }
 }
@@ -1,21 +1,39 @@
 use area::Area;
 use space::*;
+use terrain::Terrain;
 
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Appearance {
     Floor,
     Bot,
+    // A bot on the viewer's own team. Other bots look like Bot.
+    Ally,
     Block,
     Abyss,
+    Ice,
+    Conveyor(Direction),
+    Teleporter,
+    Crumbling,
+    // Out of sight under the round's rules.
+    Unknown,
 }
 impl Area {
     pub fn appearance_at(&self, focus: Position) -> Appearance {
-        // uses area.positions, area.appearances
+        // uses area.positions, area.appearances, area.terrain
         
         match self.positions.at(focus) {
-            None => Appearance::Floor,
+            None => self.terrain_at(focus).map_or(Appearance::Floor, terrain_appearance),
             Some(entity) => self.appearances.of(entity).unwrap_or(Appearance::Floor),
         }
     }
 }
+
+fn terrain_appearance(terrain: Terrain) -> Appearance {
+    match terrain {
+        Terrain::Ice => Appearance::Ice,
+        Terrain::Conveyor(direction) => Appearance::Conveyor(direction),
+        Terrain::Teleporter(..) => Appearance::Teleporter,
+        Terrain::Crumbling(..) => Appearance::Crumbling,
+    }
+}
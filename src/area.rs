@@ -1,11 +1,21 @@
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
+use std::time::Duration;
 use appearance::*;
 use space::*;
 use space::Direction::*;
 use entity::*;
+use positioned::*;
 use pushable::*;
 use action::*;
 use notification::*;
+use session::*;
+use terrain::*;
+use event::*;
+use replay::*;
+use team::*;
+use protocol::*;
+use handshake::*;
 
 
 pub fn make_bot(area: &mut Area, position: Position) -> Entity {
@@ -31,51 +41,122 @@ pub fn make_abyss(area: &mut Area, position: Position) -> Entity {
 }
 
 
-pub struct Area {
-    pub positions: Components<Position>,
-    pub appearances: Components<Appearance>,
-    pub pushables: Components<Pushable>,
-    pub inputs: Components<Box<Read>>,
-    pub outputs: Components<Box<Write>>,
-    pub cooldowns: Components<u8>,
-    pub participants_in_waiting: Vec<(Box<Read>, Box<Write>)>,
-    pub entities: Entities,
+// A participant whose bot was destroyed, waiting for the round to end.
+pub struct Fallen {
+    pub input: Box<Read>,
+    pub output: Box<Write>,
+    pub name: String,
+    pub team: Option<Team>,
+    pub timeout: Option<Duration>,
+    pub protocol: Protocol,
+    pub capabilities: u8,
+    pub tick: u32,
 }
-impl Area {
-    pub fn new() -> Area {
-        Area {
-            positions: Components::new(),
-            appearances: Components::new(),
-            pushables: Components::new(),
-            inputs: Components::new(),
-            outputs: Components::new(),
-            cooldowns: Components::new(),
-            participants_in_waiting: Vec::new(),
-            entities: Entities::new(),
+
+
+// Declares Area with one storage per component. Every component storage is created by Area::new()
+// and cleared of an entity by Area::disconnect(), so a new component only needs a line here.
+macro_rules! area {
+    (
+        components {
+            $($component:ident: $storage:ty,)*
+        }
+        fields {
+            $($field:ident: $kind:ty = $initial:expr,)*
+        }
+    ) => {
+        pub struct Area {
+            $(pub $component: $storage,)*
+            $(pub $field: $kind,)*
+        }
+        impl Area {
+            pub fn new() -> Area {
+                Area {
+                    $($component: <$storage>::new(),)*
+                    $($field: $initial,)*
+                }
+            }
+            
+            pub fn disconnect(&mut self, entity: Entity) {
+                $(Storage::despawn(&mut self.$component, entity);)*
+                self.entities.destroy(entity);
+            }
         }
     }
-    
-    pub fn remove(&mut self, entity: Entity) {
+}
+
+area! {
+    components {
+        positions: Positions,
+        appearances: Components<Appearance>,
+        pushables: Components<Pushable>,
+        inputs: Components<Box<Read>>,
+        outputs: Components<Box<Write>>,
+        cooldowns: Components<u8>,
+        names: Components<String>,
+        teams: Components<Team>,
+        // How long each participant has to send a command once it's told it's its turn
+        timeouts: Components<Duration>,
+        // How each participant's commands and notifications are put into bytes
+        protocols: Components<Protocol>,
+        // The optional parts of the protocol each participant can be sent, as CAPABILITY_ flags
+        capabilities: Components<u8>,
+        // Commands on their way from bots that have been told it's their turn
+        turns: Components<CommandReader>,
+    }
+    fields {
+        participants_in_waiting: Vec<Fallen> = Vec::new(),
+        // Names of participants dropped for hanging up or misbehaving, who are placed last
+        disconnected: Vec<String> = Vec::new(),
+        entities: Entities = Entities::new(),
+        ticks: u32 = 0,
+        // Ticks on which any bot acted, which rotating initiative goes by
+        acting_ticks: u32 = 0,
+        seed: Seed = 0,
+        terrain: HashMap<Position, Terrain> = HashMap::new(),
+        rules: Rules = Rules::new(),
+        events: VecDeque<TimedEvent> = VecDeque::new(),
+        recording: Option<Recording> = None,
+    }
+}
+
+impl Area {
+    // The killer is credited if the entity was a participant's bot and it didn't destroy itself.
+    pub fn remove(&mut self, entity: Entity, killer: Option<Entity>) {
         self.notify(entity, Notification::YouDied);
         
         match (self.inputs.detach(entity), self.outputs.detach(entity)) {
             (Some(input), Some(output)) => {
-                self.participants_in_waiting.push((input, output));
+                self.participants_in_waiting.push(Fallen {
+                    input: input,
+                    output: output,
+                    name: self.names.of(entity).unwrap_or(String::new()),
+                    team: self.teams.of(entity),
+                    timeout: self.timeouts.of(entity),
+                    protocol: self.protocols.of(entity).unwrap_or(Protocol::Binary),
+                    capabilities: self.capabilities.of(entity).unwrap_or(CAPABILITIES),
+                    tick: self.ticks,
+                });
+                
+                if let Some(killer) = killer.filter(|killer| *killer != entity) {
+                    self.emit(Event::Killed {
+                        entity: entity,
+                        killer: killer,
+                    });
+                    // knocking off a teammate earns no credit
+                    if !same_team(self.teams.of(killer), self.teams.of(entity)) {
+                        self.record_kill(killer, entity);
+                        if self.capable(killer, CAPABILITY_KILLS) {
+                            self.notify(killer, Notification::YouKilled);
+                        }
+                    }
+                }
             },
             _ => (), // TODO: change tests to allow debug_unreachable!() here
         }
         
         self.disconnect(entity);
     }
-    
-    pub fn disconnect(&mut self, entity: Entity) {
-        self.positions.detach(entity);
-        self.appearances.detach(entity);
-        self.pushables.detach(entity);
-        self.inputs.detach(entity);
-        self.outputs.detach(entity);
-        self.cooldowns.detach(entity);
-    }
 }
 
 
@@ -289,3 +370,35 @@ fn squish_indirectly() {
         }
     }
 }
+
+#[test]
+fn stale_entity_after_disconnect() {
+    let (a, b, _, mut area) = test_data();
+    
+    let bot = make_bot(&mut area, a);
+    area.disconnect(bot);
+    let block = make_block(&mut area, b);
+    
+    assert!(bot != block);
+    assert!(!area.entities.alive(bot));
+    assert_eq!(area.positions.of(bot), None);
+    assert_eq!(area.appearances.of(bot), None);
+    assert_eq!(area.positions.of(block), Some(b));
+}
+
+#[test]
+fn disconnection_detaches_everything() {
+    let (a, _, _, mut area) = test_data();
+    
+    let bot = make_bot(&mut area, a);
+    area.cooldowns.attach(bot, 3);
+    area.names.attach(bot, "bot".to_string());
+    area.disconnect(bot);
+    
+    assert!(!area.positions.attached(bot));
+    assert!(!area.appearances.attached(bot));
+    assert!(!area.pushables.attached(bot));
+    assert!(!area.cooldowns.attached(bot));
+    assert!(!area.names.attached(bot));
+    assert!(!area.positions.occupied(a));
+}
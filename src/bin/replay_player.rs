@@ -0,0 +1,133 @@
+extern crate squish_the_bots;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+use squish_the_bots::appearance::Appearance;
+use squish_the_bots::area::Area;
+use squish_the_bots::entity::Entity;
+use squish_the_bots::replay::*;
+use squish_the_bots::space::Direction::*;
+
+
+fn main() {
+    let command = env::args().nth(0).unwrap();
+    let path = match env::args().nth(1) {
+        None => panic!("Usage: {} FILE [--check]", command),
+        Some(path) => path,
+    };
+    let check_only = env::args().nth(2).map_or(false, |arg| arg == "--check");
+    
+    let replay = match load_replay(Path::new(&path)) {
+        Err(error) => panic!("Could not load replay: {}", error),
+        Ok(replay) => replay,
+    };
+    
+    if !check_only {
+        play(&replay);
+    }
+    
+    match verify_replay(&replay) {
+        Ok(()) => println!("Verified: the round plays out the same way again."),
+        Err(message) => {
+            println!("The replay does not match the simulation: {}", message);
+            process::exit(1);
+        }
+    }
+}
+
+
+fn play(replay: &Replay) {
+    println!("{} map, seed {}", replay.map_name, replay.seed);
+    for (index, bot) in replay.bots.iter().enumerate() {
+        println!("  {} {}", label(index), bot.name);
+    }
+    
+    let (mut area, bots) = replay_area(replay);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut frames = replay.frames.iter().peekable();
+    
+    while !area.round_over() {
+        let tick = area.ticks;
+        area.act_tick();
+        
+        let frame = match frames.peek() {
+            Some(frame) if frame.tick == tick => frames.next().unwrap(),
+            _ => continue,
+        };
+        
+        println!("");
+        println!("Tick {}", tick);
+        print!("{}", render(&area, &bots, replay));
+        for exchange in &frame.exchanges {
+            let protocol = replay.bots[exchange.bot].protocol;
+            let mut described = vec![];
+            if !exchange.sent.is_empty() {
+                described.push(describe_sent(&exchange.sent, protocol));
+            }
+            let received = describe_received(&exchange.received, protocol);
+            if !received.is_empty() {
+                described.push(format!("-> {}", received));
+            }
+            if !described.is_empty() {
+                println!("  {}: {}", label(exchange.bot), described.join(" "));
+            }
+        }
+        
+        print!("Enter for the next tick, q to quit: ");
+        drop(io::stdout().flush());
+        match lines.next() {
+            Some(Ok(ref line)) if line.trim() != "q" => (),
+            _ => return,
+        }
+    }
+    
+    println!("");
+    println!("Round over after {} ticks.", area.ticks);
+    for &(ref name, place) in &replay.placements {
+        println!("  {}: {}", place, name);
+    }
+}
+
+fn label(bot: usize) -> char {
+    (b'A' + (bot % 26) as u8) as char
+}
+
+fn render(area: &Area, bots: &[Entity], replay: &Replay) -> String {
+    let width = replay.rows.iter().map(|row| row.len()).max().unwrap_or(0) as i32;
+    let height = replay.rows.len() as i32;
+    
+    let mut result = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            let position = replay.origin + East * x + South * y;
+            let bot = bots.iter().position(|bot| area.positions.of(*bot) == Some(position));
+            result.push(match bot {
+                Some(index) => label(index),
+                None => glyph(area.appearance_at(position)),
+            });
+        }
+        result.push('\n');
+    }
+    result
+}
+
+fn glyph(appearance: Appearance) -> char {
+    match appearance {
+        Appearance::Floor => '.',
+        Appearance::Bot | Appearance::Ally => '?',
+        Appearance::Block => '#',
+        Appearance::Abyss => '~',
+        Appearance::Ice => '=',
+        Appearance::Conveyor(North) => '^',
+        Appearance::Conveyor(East) => '>',
+        Appearance::Conveyor(South) => 'v',
+        Appearance::Conveyor(West) => '<',
+        Appearance::Teleporter => '@',
+        Appearance::Crumbling => '%',
+        Appearance::Unknown => ' ',
+    }
+}
@@ -0,0 +1,129 @@
+extern crate getopts;
+extern crate squish_the_bots;
+
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use getopts::Options;
+use squish_the_bots::generator::*;
+use squish_the_bots::mapfile::*;
+use squish_the_bots::resolution::*;
+use squish_the_bots::session::*;
+use squish_the_bots::tournament::*;
+
+
+fn main() {
+    let mut args = env::args();
+    let program = args.next().unwrap();
+    let args = args.collect::<Vec<_>>();
+    
+    let mut options = Options::new();
+    options.optopt("f", "format", "round-robin (the default) or swiss.", "FORMAT");
+    options.optopt("r", "rounds", "Rounds each pairing plays. Defaults to 10.", "ROUNDS");
+    options.optopt("p", "passes", "How many times everyone is paired up in a Swiss tournament.",
+                   "PASSES");
+    options.optopt("", "seed", "Seed of the first round. Later rounds count up from it.", "SEED");
+    options.optopt("m", "maps",
+                   "Comma-separated map styles to rotate through: classic, maze, islands, open.",
+                   "STYLES");
+    options.optopt("", "map-directory", "Rotate through the .map files in this directory instead.",
+                   "DIR");
+    options.optopt("", "view-range", "Hide squares further than this many steps from each bot.",
+                   "SQUARES");
+    options.optflag("", "line-of-sight", "Hide squares that are behind bots and blocks.");
+    options.optopt("", "initiative", "Who moves first each tick: fixed, rotating or random.",
+                   "POLICY");
+    options.optflag("", "simultaneous", "Carry out every bot's move at the same time each tick.");
+    options.optopt("", "tick-limit", "End each round in a draw after this many ticks.", "TICKS");
+    
+    let usage = || {
+        let brief = format!("{} [options] BOT BOT...\nBots: hunter, drifter", program);
+        println!("{}", options.usage(&brief));
+    };
+    let matches = match options.parse(&args) {
+        Ok(ref matches) if matches.free.len() >= 2 => matches.clone(),
+        _ => return usage(),
+    };
+    
+    let mut entrants: Vec<Entrant> = vec![];
+    for (index, name) in matches.free.iter().enumerate() {
+        let strategy = match strategy(name) {
+            None => panic!("Unknown bot \"{}\".", name),
+            Some(strategy) => strategy,
+        };
+        // the same bot can enter more than once
+        let copies = matches.free[..index].iter().filter(|other| *other == name).count();
+        let name = match copies {
+            0 => name.clone(),
+            _ => format!("{} {}", name, copies + 1),
+        };
+        entrants.push(Entrant::new(name, strategy));
+    }
+    
+    let mut config = TournamentConfig::new();
+    if let Some(rounds) = matches.opt_str("rounds") {
+        config.rounds_per_match = match rounds.parse() {
+            Err(..) => panic!("Invalid number of rounds."),
+            Ok(rounds) => rounds,
+        };
+    }
+    if let Some(seed) = matches.opt_str("seed") {
+        config.seed = match seed.parse::<Seed>() {
+            Err(..) => panic!("Invalid seed."),
+            Ok(seed) => seed,
+        };
+    }
+    if let Some(styles) = matches.opt_str("maps") {
+        config.maps = styles.split(',').map(|style| match map_generator(style.trim()) {
+            None => panic!("Unknown map style \"{}\".", style),
+            Some(generator) => generator,
+        }).collect();
+    }
+    if let Some(directory) = matches.opt_str("map-directory") {
+        config.maps = match load_map_directory(Path::new(&directory)) {
+            Err((path, error)) => panic!("Could not load map {}: {}", path, error),
+            Ok(ref maps) if maps.is_empty() => panic!("No .map files in {}.", directory),
+            Ok(maps) => maps.into_iter().map(|map| Arc::new(map) as Arc<MapGenerator>).collect(),
+        };
+    }
+    config.rules.view_range = matches.opt_str("view-range").map(|range| match range.parse() {
+        Err(..) => panic!("Invalid view range."),
+        Ok(range) => range,
+    });
+    config.rules.line_of_sight = matches.opt_present("line-of-sight");
+    if let Some(name) = matches.opt_str("initiative") {
+        config.rules.initiative = match initiative(&name) {
+            None => panic!("Unknown initiative \"{}\".", name),
+            Some(initiative) => initiative,
+        };
+    }
+    config.rules.simultaneous = matches.opt_present("simultaneous");
+    if let Some(limit) = matches.opt_str("tick-limit") {
+        config.rules.tick_limit = match limit.parse() {
+            Err(..) => panic!("Invalid tick limit."),
+            Ok(limit) => Some(limit),
+        };
+    }
+    
+    let standings = match matches.opt_str("format").as_ref().map(|format| &**format) {
+        None | Some("round-robin") => round_robin(&entrants, &config),
+        Some("swiss") => {
+            let passes = match matches.opt_str("passes").map(|passes| passes.parse()) {
+                None => entrants.len() as u32 - 1,
+                Some(Err(..)) => panic!("Invalid number of passes."),
+                Some(Ok(passes)) => passes,
+            };
+            swiss(&entrants, &config, passes)
+        }
+        Some(format) => panic!("Unknown format \"{}\".", format),
+    };
+    
+    match standings {
+        Err(error) => panic!("Could not run the tournament: {}", error),
+        Ok(standings) => {
+            println!("Win rates of each row against each column, with 95% confidence intervals:");
+            print!("{}", standings.matrix());
+        }
+    }
+}
@@ -4,28 +4,84 @@ use std::io::{Read, Write};
 use std::collections::HashMap;
 
 
+// The generation tells apart the entities that have used the same index over time so that a
+// handle to a destroyed entity never refers to whatever took its place.
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug, PartialOrd, Ord)]
-pub struct Entity(u32);
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
 
 
 pub struct Entities {
-    next_id: u32,
+    generations: Vec<u32>,
+    free: Vec<u32>,
 }
 impl Entities {
     pub fn new() -> Entities {
         Entities {
-            next_id: 0,
+            generations: Vec::new(),
+            free: Vec::new(),
         }
     }
     
     pub fn make(&mut self) -> Entity {
-        let result = Entity(self.next_id);
-        self.next_id += 1;
-        result
+        match self.free.pop() {
+            Some(index) => Entity {
+                index: index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                self.generations.push(0);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+    
+    pub fn alive(&self, entity: Entity) -> bool {
+        self.generations.get(entity.index as usize) == Some(&entity.generation)
+    }
+    
+    // Makes the entity's index available again. Does nothing if it was already destroyed.
+    pub fn destroy(&mut self, entity: Entity) {
+        if !self.alive(entity) {
+            return;
+        }
+        self.generations[entity.index as usize] += 1;
+        self.free.push(entity.index);
     }
 }
 
 
+// Anything that holds a component for some entities.
+pub trait Storage {
+    fn despawn(&mut self, entity: Entity);
+    
+    fn has(&self, entity: Entity) -> bool;
+    
+    fn count(&self) -> usize;
+    
+    fn entities(&self) -> Vec<Entity>;
+}
+
+// The entities that have a component in every one of the storages, in order.
+pub fn with_all(storages: &[&Storage]) -> Vec<Entity> {
+    let smallest = match storages.iter().min_by_key(|storage| storage.count()) {
+        None => return vec![],
+        Some(storage) => storage,
+    };
+    let mut result = smallest.entities()
+                             .into_iter()
+                             .filter(|entity| storages.iter().all(|storage| storage.has(*entity)))
+                             .collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
+
 pub struct Components<R> {
     pub contents: HashMap<Entity, R>
 }
@@ -57,6 +113,24 @@ impl<R> Components<R> {
     }
 }
 
+impl<R> Storage for Components<R> {
+    fn despawn(&mut self, entity: Entity) {
+        self.detach(entity);
+    }
+    
+    fn has(&self, entity: Entity) -> bool {
+        self.attached(entity)
+    }
+    
+    fn count(&self) -> usize {
+        self.contents.len()
+    }
+    
+    fn entities(&self) -> Vec<Entity> {
+        self.contents.keys().map(Clone::clone).collect()
+    }
+}
+
 impl<R: Clone> Components<R> {
     pub fn of(&self, entity: Entity) -> Option<R> {
         self.of_ref(entity).map(Clone::clone)
@@ -68,9 +142,72 @@ impl<R: Clone> Components<R> {
 #[derive(Hash, PartialEq, Eq, Debug)]
 struct NonCopyInteger(i32);
 
+#[test]
+fn reuse() {
+    let mut entities = Entities::new();
+    let a = entities.make();
+    let b = entities.make();
+    assert!(a != b);
+    assert!(entities.alive(a));
+    
+    entities.destroy(a);
+    entities.destroy(a);
+    assert!(!entities.alive(a));
+    assert!(entities.alive(b));
+    
+    let c = entities.make();
+    assert_eq!(c.index, a.index);
+    assert!(c != a);
+    assert!(entities.alive(c));
+    assert!(!entities.alive(a));
+    
+    let d = entities.make();
+    assert!(d.index != a.index && d.index != b.index);
+}
+
+#[test]
+fn stale_handles() {
+    let mut entities = Entities::new();
+    let mut group = Components::new();
+    
+    let old = entities.make();
+    group.attach(old, 1);
+    group.detach(old);
+    entities.destroy(old);
+    
+    let new = entities.make();
+    group.attach(new, 2);
+    assert_eq!(group.of(old), None);
+    assert!(!group.attached(old));
+    assert_eq!(group.detach(old), None);
+    assert_eq!(group.of(new), Some(2));
+}
+
+#[test]
+fn querying() {
+    let mut entities = Entities::new();
+    let mut numbers = Components::new();
+    let mut words = Components::new();
+    
+    let a = entities.make();
+    let b = entities.make();
+    let c = entities.make();
+    numbers.attach(a, 1);
+    numbers.attach(b, 2);
+    words.attach(c, "c");
+    words.attach(b, "b");
+    
+    assert_eq!(with_all(&[&numbers, &words]), vec![b]);
+    assert_eq!(with_all(&[&numbers]), vec![a, b]);
+    assert_eq!(with_all(&[]), vec![]);
+    
+    numbers.despawn(b);
+    assert_eq!(with_all(&[&words, &numbers]), vec![]);
+}
+
 #[test]
 fn attachment() {
-    let entity = Entity(0);
+    let entity = Entities::new().make();
     let mut group = Components::new();
     assert!(!group.attached(entity));
     assert_eq!(group.of(entity), None);
@@ -89,7 +226,7 @@ fn attachment() {
 
 #[test]
 fn attachment_references() {
-    let entity = Entity(0);
+    let entity = Entities::new().make();
     let mut group = Components::new();
     assert!(!group.attached(entity));
     assert_eq!(group.of_ref(entity), None);
@@ -0,0 +1,240 @@
+// Everything that happens in an area, in the order it happened. Whoever wants to watch a round
+// (spectators, replays, statistics) takes the queued events from the area as it goes. Nobody has
+// to, so only the most recent events are kept.
+
+use area::*;
+use space::*;
+use entity::*;
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    // Changed squares for any reason: walking, drilling, being pushed or being carried by terrain.
+    Moved {
+        entity: Entity,
+        from: Position,
+        to: Position,
+    },
+    // Shoved out of its square by whatever was entering it.
+    Pushed {
+        entity: Entity,
+        direction: Direction,
+    },
+    Squished {
+        entity: Entity,
+        at: Position,
+    },
+    Drilled {
+        entity: Entity,
+        at: Position,
+        driller: Entity,
+    },
+    Fell {
+        entity: Entity,
+        at: Position,
+    },
+    // Crumbling floor gave way to the abyss after whatever was on it left.
+    Crumbled {
+        at: Position,
+    },
+    // A participant's bot was destroyed by another bot, directly or by pushing something into it.
+    // Follows the event that destroyed it.
+    Killed {
+        entity: Entity,
+        killer: Entity,
+    },
+    // The participant's connection was dropped for sending garbage, taking too long or not
+    // listening.
+    Disconnected {
+        entity: Entity,
+        reason: Disconnection,
+    },
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Disconnection {
+    // Sent something that isn't a command.
+    Malformed,
+    // Didn't send a whole command before the turn's deadline.
+    TimedOut,
+    // Hung up or stopped listening.
+    Closed,
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimedEvent {
+    pub tick: u32,
+    pub event: Event,
+}
+
+
+// Older events are dropped once this many are waiting to be taken.
+pub const MAX_QUEUED_EVENTS: usize = 4096;
+
+
+impl Area {
+    pub fn emit(&mut self, event: Event) {
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        let tick = self.ticks;
+        self.events.push_back(TimedEvent {
+            tick: tick,
+            event: event,
+        });
+    }
+    
+    pub fn take_events(&mut self) -> Vec<TimedEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+#[cfg(test)]
+use std::time::Duration;
+#[cfg(test)]
+use space::Direction::*;
+#[cfg(test)]
+use terrain::Terrain;
+
+#[cfg(test)]
+fn events_of(area: &mut Area) -> Vec<Event> {
+    area.take_events().into_iter().map(|timed| timed.event).collect()
+}
+
+#[test]
+fn movement_events() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    let block = make_block(&mut area, origin + East);
+    let victim = make_bot(&mut area, origin + South);
+    let wall = make_block(&mut area, origin + South * 2);
+    make_abyss(&mut area, origin + North);
+    
+    area.go(bot, East);
+    assert_eq!(events_of(&mut area), vec![
+        Event::Pushed { entity: block, direction: East },
+        Event::Moved { entity: block, from: origin + East, to: origin + East * 2 },
+        Event::Moved { entity: bot, from: origin, to: origin + East },
+    ]);
+    
+    area.go(bot, West);
+    area.take_events();
+    area.go(bot, South);
+    assert_eq!(events_of(&mut area), vec![
+        Event::Squished { entity: victim, at: origin + South },
+        Event::Moved { entity: bot, from: origin, to: origin + South },
+    ]);
+    
+    area.drill(bot, South);
+    assert_eq!(events_of(&mut area)[0],
+               Event::Drilled { entity: wall, at: origin + South * 2, driller: bot });
+    
+    area.go(bot, North);
+    area.go(bot, North);
+    area.take_events();
+    area.go(bot, North);
+    assert_eq!(events_of(&mut area), vec![Event::Fell { entity: bot, at: origin + North }]);
+}
+
+#[test]
+fn kill_credit() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let killer = make_bot(&mut area, origin);
+    make_block(&mut area, origin + East);
+    let squished = make_bot(&mut area, origin + East * 2);
+    make_block(&mut area, origin + East * 3);
+    let drilled = make_bot(&mut area, origin + West);
+    let slid = make_bot(&mut area, origin + South);
+    area.place_terrain(origin + South * 2, Terrain::Ice);
+    area.place_terrain(origin + South * 3, Terrain::Ice);
+    make_abyss(&mut area, origin + South * 4);
+    let loner = make_bot(&mut area, origin + North * 5);
+    make_abyss(&mut area, origin + North * 6);
+    for bot in vec![killer, squished, drilled, slid, loner] {
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![])));
+        area.outputs.attach(bot, Box::new(vec![]));
+    }
+    
+    // squished by the block that was pushed into it
+    area.go(killer, East);
+    let events = events_of(&mut area);
+    assert_eq!(events[0], Event::Squished { entity: squished, at: origin + East * 2 });
+    assert_eq!(events[1], Event::Killed { entity: squished, killer: killer });
+    
+    area.go(killer, West);
+    area.drill(killer, West);
+    assert!(events_of(&mut area).contains(&Event::Killed { entity: drilled, killer: killer }));
+    
+    // pushed onto ice and slid over the edge
+    area.go(killer, East);
+    area.go(killer, South);
+    assert!(events_of(&mut area).contains(&Event::Killed { entity: slid, killer: killer }));
+    
+    area.go(loner, North);
+    assert_eq!(events_of(&mut area), vec![Event::Fell { entity: loner, at: origin + North * 6 }]);
+}
+
+#[test]
+fn ticks_and_disconnection() {
+    let mut area = Area::new();
+    let a = make_bot(&mut area, Position::default());
+    let b = make_bot(&mut area, Position::default() + South * 3);
+    area.inputs.attach(a, Box::new(Cursor::new(vec![4, 1, 2, 1])));
+    area.inputs.attach(b, Box::new(Cursor::new(vec![4, 1, 4, 1, 99])));
+    area.outputs.attach(a, Box::new(vec![]));
+    area.outputs.attach(b, Box::new(vec![]));
+    
+    area.act_all(Duration::from_millis(0));
+    let events = area.take_events();
+    assert_eq!(events, vec![
+        TimedEvent {
+            tick: 1,
+            event: Event::Moved {
+                entity: a,
+                from: Position::default(),
+                to: Position::default() + East,
+            },
+        },
+        TimedEvent {
+            tick: 2,
+            event: Event::Disconnected {
+                entity: b,
+                reason: Disconnection::Malformed,
+            },
+        },
+    ]);
+}
+
+#[test]
+fn crumbling_events() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    area.place_terrain(origin, Terrain::Crumbling(1));
+    
+    area.go(bot, East);
+    assert_eq!(events_of(&mut area), vec![
+        Event::Moved { entity: bot, from: origin, to: origin + East },
+        Event::Crumbled { at: origin },
+    ]);
+}
+
+#[test]
+fn queue_limit() {
+    let mut area = Area::new();
+    for tick in 0..MAX_QUEUED_EVENTS as u32 + 10 {
+        area.ticks = tick;
+        area.emit(Event::Crumbled { at: Position::default() });
+    }
+    let events = area.take_events();
+    assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+    assert_eq!(events[0].tick, 10);
+    assert!(area.take_events().is_empty());
+}
@@ -0,0 +1,152 @@
+// An even simpler example bot that wanders in straight lines, pushing whatever is in its way.
+// It looks before every step so that it never walks off a ledge on purpose, and it turns
+// clockwise whenever it can't go on or has gone far enough. It has no idea where anyone else is.
+
+use std::io::{self, Read, Write};
+use std::net::*;
+use std::thread;
+use handshake::*;
+
+
+const STEPS_BEFORE_TURNING: u32 = 6;
+
+// North, east, south and west, in the order the server numbers them
+const DELTAS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+
+struct Model {
+    heading: u8,
+    steps: u32,
+    // Whether the square ahead has been looked at and can be walked on
+    clear_ahead: bool,
+    moving: bool,
+}
+impl Model {
+    fn new() -> Model {
+        Model {
+            heading: 0,
+            steps: 0,
+            clear_ahead: false,
+            moving: false,
+        }
+    }
+    
+    fn turn(&mut self) {
+        self.heading = (self.heading + 1) % 4;
+        self.steps = 0;
+        self.clear_ahead = false;
+    }
+    
+    fn act(&mut self) -> Vec<u8> {
+        if self.clear_ahead {
+            self.clear_ahead = false;
+            self.moving = true;
+            vec![2, self.heading]
+        }
+        else {
+            self.moving = false;
+            let (dx, dy) = DELTAS[self.heading as usize];
+            vec![1, dx as u8, dy as u8]
+        }
+    }
+    
+    fn see(&mut self, code: u8) {
+        match code {
+            // abyss, out of sight or a teammate
+            3 | 11 | 12 => self.turn(),
+            _ => self.clear_ahead = true,
+        }
+    }
+    
+    fn success(&mut self) {
+        if !self.moving {
+            return;
+        }
+        self.steps += 1;
+        if self.steps >= STEPS_BEFORE_TURNING {
+            self.turn();
+        }
+    }
+}
+
+
+pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    
+    // so that the server knows the bot by its name instead of its address, keeping the version 1
+    // protocol that play() speaks and asking for You Killed, which it logs
+    let handshake = Handshake::new(1, CAPABILITY_KILLS, name.clone());
+    stream.write_all(&serialize_handshake(&handshake)).unwrap();
+    read_reply(&mut stream).unwrap();
+    
+    if logs {
+        println!("{}: Connected.", name);
+    }
+    
+    play(stream.try_clone().unwrap(), stream, name, logs);
+}
+
+// Plays until the server hangs up.
+pub fn play<R: Read, W: Write>(mut input: R, mut output: W, name: String, logs: bool) {
+    let log = move|message: &str| {
+        if logs {
+            println!("{}: {}", name, message);
+        }
+    };
+    
+    let mut model = Model::new();
+    loop {
+        let mut buf = [0; 1];
+        if input.read(&mut buf).unwrap() != 1 {
+            log("Disconnected.");
+            return;
+        }
+        match buf[0] {
+            1 => {
+                if output.write_all(&model.act()).is_err() {
+                    log("Disconnected.");
+                    return;
+                }
+            }
+            2 => log("Dead."),
+            3 => model.success(),
+            4 => {
+                log("It was too heavy to push.");
+                model.turn();
+            }
+            5 => {
+                log("A new round is starting.");
+                model = Model::new();
+            }
+            6 => {
+                input.read_exact(&mut buf).unwrap();
+                model.see(buf[0]);
+            }
+            7 => log("I won the round."),
+            8 => log("The round was a draw."),
+            11 => log("Got one."),
+            9 => {
+                let mut placement = [0; 2];
+                input.read_exact(&mut placement).unwrap();
+                log(&*format!("I placed {} of {}.", placement[0], placement[1]));
+            }
+            10 => {
+                input.read_exact(&mut buf).unwrap();
+                let width = buf[0] as u64 * 2 + 1;
+                io::copy(&mut (&mut input).take(width * width), &mut io::sink()).unwrap();
+            }
+            code @ _ => {
+                log(&*format!("I don't know what the server meant by that. ({})", code));
+                panic!();
+            }
+        }
+    }
+}
+
+
+pub fn start<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool)
+        -> io::Result<thread::JoinHandle<()>> {
+    thread::Builder::new().name(name.clone()).spawn(move|| {
+        run(address, name, logs);
+    })
+}
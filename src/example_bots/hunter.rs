@@ -10,6 +10,7 @@ use std::io::{self, Read, Write};
 use std::mem::transmute;
 use std::net::*;
 use std::thread;
+use handshake::*;
 use self::Direction::*;
 
 
@@ -77,6 +78,7 @@ enum Thing {
     Bot,
     Block,
     Floor,
+    Hidden,
 }
 fn code_to_thing(code: u8) -> Option<Thing> {
     match code {
@@ -84,6 +86,12 @@ fn code_to_thing(code: u8) -> Option<Thing> {
         1 => Some(Thing::Bot),
         2 => Some(Thing::Block),
         3 => Some(Thing::Abyss),
+        // ice, conveyors, teleporters and crumbling floor can all be walked on, for now
+        4...10 => Some(Thing::Floor),
+        // out of sight, so it could be anything
+        11 => Some(Thing::Hidden),
+        // a teammate, which is best left alone
+        12 => Some(Thing::Block),
         _ => None,
     }
 }
@@ -102,6 +110,10 @@ enum Sighting {
     Bot {
         last_spotted: u32,
     },
+    // Looked at but out of sight. Never walked onto, only looked at again later.
+    Hidden {
+        last_spotted: u32,
+    },
 }
 impl Sighting {
     fn new(thing: Thing, now: u32) -> Sighting {
@@ -117,6 +129,9 @@ impl Sighting {
                 last_spotted: now,
                 heavy: false,
             },
+            Thing::Hidden => Sighting::Hidden {
+                last_spotted: now,
+            },
         }
     }
     
@@ -196,7 +211,8 @@ impl Model {
                         
                         Some(Sighting::Floor { last_spotted }) |
                         Some(Sighting::Block { last_spotted, .. }) |
-                        Some(Sighting::Bot { last_spotted }) => {
+                        Some(Sighting::Bot { last_spotted }) |
+                        Some(Sighting::Hidden { last_spotted }) => {
                             if last_spotted + 40 < self.time {
                                 return (x, y)
                             }
@@ -230,7 +246,7 @@ impl Model {
             let (dx, dy) = direction.to_delta();
             match self.at_relative(dx, dy) {
                 None => result.push(Action::LookAt(dx, dy)),
-                Some(Sighting::Abyss) => continue,
+                Some(Sighting::Abyss) | Some(Sighting::Hidden {..}) => continue,
                 Some(Sighting::Floor {..}) => result.push(Action::Move(direction)),
                 Some(Sighting::Bot {..}) => return vec![Action::Drill(direction)],
                 Some(Sighting::Block { heavy: true, .. }) => result.push(Action::Drill(direction)),
@@ -265,6 +281,8 @@ impl Model {
                 let there = (there.0 * 2, there.1 * 2);
                 self.sightings.insert(there, Sighting::heavy(self.time));
             },
+            // another bot got in the way in a round where everyone moves at once
+            Some(Action::Drill(..)) => (),
             _ => panic!(),
         }
     }
@@ -272,21 +290,35 @@ impl Model {
 
 
 pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    
+    // so that the server knows the bot by its name instead of its address, keeping the version 1
+    // protocol that play() speaks along with the allies and You Killed that it understands
+    let handshake = Handshake::new(1, CAPABILITY_ALLIES | CAPABILITY_KILLS, name.clone());
+    stream.write_all(&serialize_handshake(&handshake)).unwrap();
+    read_reply(&mut stream).unwrap();
+    
+    if logs {
+        println!("{}: Connected.", name);
+    }
+    
+    play(stream.try_clone().unwrap(), stream, name, logs);
+}
+
+// Plays until the server hangs up.
+pub fn play<R: Read, W: Write>(mut input: R, mut output: W, name: String, logs: bool) {
     let log = move|message: &str| {
         if logs {
             println!("{}: {}", name, message);
         }
     };
     
-    let mut stream = TcpStream::connect(address).unwrap();
-    
-    log("Connected.");
-    
     let mut model = Model::new();
     loop {
         let mut buf = [0; 1];
-        if stream.read(&mut buf).unwrap() != 1 {
-            panic!();
+        if input.read(&mut buf).unwrap() != 1 {
+            log("Disconnected.");
+            return;
         }
         match buf[0] {
             1 => {
@@ -303,7 +335,7 @@ pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool)
                             continue;
                         }
                         match model.at_relative(x, y) {
-                            None => view.push('?'),
+                            None | Some(Sighting::Hidden {..}) => view.push('?'),
                             Some(Sighting::Floor {..}) => view.push(' '),
                             Some(Sighting::Abyss) => view.push('0'),
                             Some(Sighting::Bot {..}) => view.push('!'),
@@ -317,7 +349,7 @@ pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool)
                 
                 let action = model.search();
                 log(&*format!("{}", action));
-                stream.write_all(&action.serialize()).unwrap();
+                output.write_all(&action.serialize()).unwrap();
                 model.last_action = Some(action);
                 model.tick();
             }
@@ -338,7 +370,7 @@ pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool)
                 model = Model::new();
             }
             6 => {
-                if stream.read(&mut buf).unwrap() != 1 {
+                if input.read(&mut buf).unwrap() != 1 {
                     panic!();
                 }
                 
@@ -360,7 +392,21 @@ pub fn run<A: ToSocketAddrs+Send+'static>(address: A, name: String, logs: bool)
                         panic!();
                     }
                 }
-                
+            
+            }
+            7 => {
+                log("I won the round.");
+            }
+            8 => {
+                log("The round was a draw.");
+            }
+            9 => {
+                let mut placement = [0; 2];
+                input.read_exact(&mut placement).unwrap();
+                log(&*format!("I placed {} of {}.", placement[0], placement[1]));
+            }
+            11 => {
+                log("Got one.");
             }
             code @ _ => {
                 log(&*format!("I don't know what the server meant by that. ({})", code));
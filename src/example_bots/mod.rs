@@ -1 +1,2 @@
+pub mod drifter;
 pub mod hunter;
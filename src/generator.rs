@@ -0,0 +1,303 @@
+use rand::{Rng, StdRng};
+use std::collections::HashSet;
+use std::sync::Arc;
+use area::*;
+use space::*;
+use space::Direction::*;
+
+
+pub trait MapGenerator: Send + Sync {
+    fn name(&self) -> &str;
+    
+    fn max_participants(&self) -> Option<usize> {
+        None
+    }
+    
+    // Returns an area without any bots in it and at least one spawn point per participant.
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>);
+}
+
+
+pub fn map_generator(name: &str) -> Option<Arc<MapGenerator>> {
+    match name {
+        "classic" => Some(Arc::new(Classic)),
+        "maze" => Some(Arc::new(Maze)),
+        "islands" => Some(Arc::new(Islands)),
+        "open" => Some(Arc::new(OpenArena)),
+        _ => None,
+    }
+}
+
+
+// Random blocks scattered over a square.
+pub struct Classic;
+impl MapGenerator for Classic {
+    fn name(&self) -> &str {
+        "classic"
+    }
+    
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>) {
+        let length = 10 + participants as i32;
+        let bounds = Rectangle::wh(East * length + South * length);
+        let mut area = Area::new();
+        
+        let limit = rng.gen_range(bounds.area() / 4, bounds.area() * 7 / 8) as usize;
+        
+        let mut positions = random_unoccupied_position_list(&area, bounds, limit, rng);
+        let spawn_count = positions.len() - participants.min(positions.len());
+        let spawns = positions.split_off(spawn_count);
+        
+        for position in positions {
+            make_block(&mut area, position);
+        }
+        
+        surround_with_abyss(&mut area, bounds);
+        
+        (area, spawns)
+    }
+}
+
+
+// Corridors one square wide with block walls and a few shortcuts knocked through them.
+pub struct Maze;
+impl MapGenerator for Maze {
+    fn name(&self) -> &str {
+        "maze"
+    }
+    
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>) {
+        let cells = 3 + participants as i32;
+        let length = cells * 2 + 1;
+        let bounds = Rectangle::wh(East * length + South * length);
+        let mut area = Area::new();
+        
+        let cell_position = |x: i32, y: i32| Position::new(x * 2 + 1, y * 2 + 1);
+        
+        let mut open = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(0, 0)];
+        visited.insert((0, 0));
+        open.insert(cell_position(0, 0));
+        
+        while let Some(&(x, y)) = stack.last() {
+            let mut neighbors = Direction::all().iter()
+                .map(|direction| {
+                    let offset = Position::default() + *direction;
+                    (x + offset.x, y + offset.y)
+                })
+                .filter(|&(nx, ny)| {
+                    nx >= 0 && ny >= 0 && nx < cells && ny < cells && !visited.contains(&(nx, ny))
+                })
+                .collect::<Vec<_>>();
+            
+            if neighbors.is_empty() {
+                stack.pop();
+                continue;
+            }
+            rng.shuffle(&mut neighbors);
+            let (nx, ny) = neighbors[0];
+            
+            visited.insert((nx, ny));
+            open.insert(cell_position(nx, ny));
+            open.insert(Position::new(x + nx + 1, y + ny + 1));
+            stack.push((nx, ny));
+        }
+        
+        for position in bounds {
+            if open.contains(&position) {
+                continue;
+            }
+            let border = position.x == 0 || position.y == 0 ||
+                position.x == length - 1 || position.y == length - 1;
+            if !border && rng.gen_weighted_bool(8) {
+                continue;
+            }
+            make_block(&mut area, position);
+        }
+        
+        surround_with_abyss(&mut area, bounds);
+        
+        let mut spawns = (0..cells * cells)
+            .map(|index| cell_position(index % cells, index / cells))
+            .collect::<Vec<_>>();
+        rng.shuffle(&mut spawns);
+        spawns.truncate(participants);
+        
+        (area, spawns)
+    }
+}
+
+
+// Patches of floor over the abyss, chained together by narrow bridges.
+pub struct Islands;
+impl MapGenerator for Islands {
+    fn name(&self) -> &str {
+        "islands"
+    }
+    
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>) {
+        let length = 12 + participants as i32 * 2;
+        let bounds = Rectangle::wh(East * length + South * length);
+        let mut area = Area::new();
+        
+        let inner = Rectangle::xywh(Position::new(2, 2), East * (length - 4) + South * (length - 4));
+        let mut centers = inner.into_iter().collect::<Vec<_>>();
+        rng.shuffle(&mut centers);
+        centers.truncate(participants.max(2));
+        
+        let mut floor = HashSet::new();
+        for center in &centers {
+            let radius = rng.gen_range(1, 3);
+            let island = Rectangle::corners(*center + North * radius + West * radius,
+                                            *center + South * radius + East * radius);
+            for position in island {
+                if bounds.contains(position) {
+                    floor.insert(position);
+                }
+            }
+        }
+        for pair in centers.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let corner = Position::new(to.x, from.y);
+            for position in Rectangle::corners(from, corner).into_iter()
+                    .chain(Rectangle::corners(corner, to)) {
+                floor.insert(position);
+            }
+        }
+        
+        for position in bounds {
+            if !floor.contains(&position) {
+                make_abyss(&mut area, position);
+            }
+            else if !centers.contains(&position) && rng.gen_weighted_bool(6) {
+                make_block(&mut area, position);
+            }
+        }
+        
+        surround_with_abyss(&mut area, bounds);
+        
+        centers.truncate(participants);
+        (area, centers)
+    }
+}
+
+
+// A wide floor with only a handful of blocks for cover.
+pub struct OpenArena;
+impl MapGenerator for OpenArena {
+    fn name(&self) -> &str {
+        "open"
+    }
+    
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>) {
+        let length = 8 + participants as i32 * 2;
+        let bounds = Rectangle::wh(East * length + South * length);
+        let mut area = Area::new();
+        
+        let mut positions = random_unoccupied_position_list(&area, bounds, bounds.area() as usize,
+                                                            rng);
+        let spawn_count = positions.len() - participants.min(positions.len());
+        let spawns = positions.split_off(spawn_count);
+        
+        let limit = bounds.area() as usize / 12;
+        for position in positions.into_iter().take(limit) {
+            make_block(&mut area, position);
+        }
+        
+        surround_with_abyss(&mut area, bounds);
+        
+        (area, spawns)
+    }
+}
+
+
+pub fn surround_with_abyss(area: &mut Area, bounds: Rectangle) {
+    let outer_bounds = Rectangle::corners(
+        bounds.topleft() + West + North,
+        bounds.topleft() + bounds.size() + South + East
+    );
+    for position in outer_bounds {
+        if bounds.contains(position) {
+            continue;
+        }
+        make_abyss(area, position);
+    }
+}
+
+pub fn unoccupied_positions(area: &Area, bounds: Rectangle) -> Vec<Position> {
+    bounds.into_iter().filter(|position| !area.positions.occupied(*position)).collect()
+}
+
+pub fn random_unoccupied_position<R: Rng>(area: &Area, bounds: Rectangle, rng: &mut R)
+        -> Option<Position> {
+    rng.choose(&unoccupied_positions(area, bounds)).map(Clone::clone)
+}
+
+pub fn random_unoccupied_position_list<R: Rng>(area: &Area, bounds: Rectangle, limit: usize,
+                                               rng: &mut R) -> Vec<Position> {
+    // TODO: this only works on new, empty areas
+    let mut positions = bounds.into_iter().collect::<Vec<_>>();
+    rng.shuffle(&mut positions);
+    positions.into_iter().take(limit).collect::<Vec<_>>()
+}
+
+
+#[cfg(test)]
+use session::seeded_rng;
+#[cfg(test)]
+use appearance::Appearance;
+
+#[test]
+fn emptiness() {
+    let mut rng = seeded_rng(0);
+    
+    let area = Area::new();
+    let rect = Rectangle::corners(Position::default(), Position::default());
+    assert_eq!(unoccupied_positions(&area, rect), vec![Position::default()]);
+    for _ in 0..1000 {
+        assert_eq!(random_unoccupied_position(&area, rect, &mut rng), Some(Position::default()));
+    }
+    
+    let area = Area::new();
+    let rect = Rectangle::corners(Position::default(), Position::default() + South);
+    assert_eq!(unoccupied_positions(&area, rect),
+               vec![Position::default(), Position::default() + South]);
+    for _ in 0..1000 {
+        let position = random_unoccupied_position(&area, rect, &mut rng);
+        assert!(position == Some(Position::default()) || position == Some(Position::default() + South));
+    }
+}
+
+#[test]
+fn spawns_are_connected() {
+    for name in vec!["classic", "maze", "islands", "open"] {
+        let generator = map_generator(name).unwrap();
+        for seed in 0..30 {
+            let participants = seed as usize % 8 + 1;
+            let (area, spawns) = generator.generate(participants, &mut seeded_rng(seed));
+            
+            assert_eq!(spawns.len(), participants, "{}", name);
+            for spawn in &spawns {
+                assert!(!area.positions.occupied(*spawn), "{} {:?}", name, spawn);
+                assert_eq!(spawns.iter().filter(|other| *other == spawn).count(), 1);
+            }
+            
+            // blocks can be drilled through but the abyss can't be crossed
+            let mut reached = HashSet::new();
+            let mut frontier = vec![spawns[0]];
+            while let Some(position) = frontier.pop() {
+                if reached.contains(&position) ||
+                        area.appearance_at(position) == Appearance::Abyss {
+                    continue;
+                }
+                reached.insert(position);
+                for direction in Direction::all().iter() {
+                    frontier.push(position + *direction);
+                }
+            }
+            for spawn in &spawns {
+                assert!(reached.contains(spawn), "{} seed {}", name, seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// The greeting a bot can send as soon as it connects, before anything else:
+//
+// - the four bytes of MAGIC
+// - the version of the protocol it speaks
+// - one byte of CAPABILITY_ flags for the optional parts of the protocol it understands
+// - the length of its name in bytes, then the name in UTF-8
+//
+// The server answers with MAGIC, the version it accepted and the flags it will use, which are the
+// ones the bot asked for that the server has. Bots that skip the handshake speak version 1 and are
+// known by their address, and get none of the optional parts.
+
+use std::io::{self, Read, Write};
+use area::*;
+use entity::*;
+
+
+// Never the first byte of a command, so the server can tell a handshake from a bot without one.
+pub const MAGIC: [u8; 4] = [0xB0, 0x75, 0x5B, 0x07];
+
+// The newest version the server speaks. Version 2 frames every message; see protocol.rs.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+// The Scan command and You Scanned.
+pub const CAPABILITY_SCAN: u8 = 1;
+// You See can be answered with Ally in team rounds.
+pub const CAPABILITY_ALLIES: u8 = 2;
+// You Killed.
+pub const CAPABILITY_KILLS: u8 = 4;
+
+// Every optional part of the protocol this server has.
+pub const CAPABILITIES: u8 = CAPABILITY_SCAN | CAPABILITY_ALLIES | CAPABILITY_KILLS;
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Handshake {
+    pub version: u8,
+    // CAPABILITY_ flags
+    pub capabilities: u8,
+    // Empty when the bot would rather be known by its address.
+    pub name: String,
+}
+impl Handshake {
+    pub fn new(version: u8, capabilities: u8, name: String) -> Handshake {
+        Handshake {
+            version: version,
+            capabilities: capabilities,
+            name: name,
+        }
+    }
+}
+
+
+impl Area {
+    // Whether the bot can be sent the optional part of the protocol. Bots are taken to understand
+    // all of them unless told otherwise.
+    pub fn capable(&self, bot: Entity, capability: u8) -> bool {
+        self.capabilities.of(bot).unwrap_or(CAPABILITIES) & capability != 0
+    }
+}
+
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Names longer than 255 bytes are cut short, at a character boundary.
+pub fn serialize_handshake(handshake: &Handshake) -> Vec<u8> {
+    let mut length = handshake.name.len().min(255);
+    while !handshake.name.is_char_boundary(length) {
+        length -= 1;
+    }
+    
+    let mut result = MAGIC.to_vec();
+    result.push(handshake.version);
+    result.push(handshake.capabilities);
+    result.push(length as u8);
+    result.extend_from_slice(handshake.name[..length].as_bytes());
+    result
+}
+
+pub fn read_handshake(input: &mut Read) -> io::Result<Handshake> {
+    let mut magic = [0; 4];
+    try!(input.read_exact(&mut magic));
+    if magic != MAGIC {
+        return Err(invalid("Not a handshake."));
+    }
+    
+    let mut header = [0; 3];
+    try!(input.read_exact(&mut header));
+    if header[0] == 0 {
+        return Err(invalid("There is no protocol version 0."));
+    }
+    
+    let mut name = vec![0; header[2] as usize];
+    try!(input.read_exact(&mut name));
+    match String::from_utf8(name) {
+        Err(..) => Err(invalid("Name is not UTF-8.")),
+        Ok(name) => Ok(Handshake::new(header[0], header[1], name)),
+    }
+}
+
+// Returns the version both sides speak and the capabilities the bot gets.
+pub fn accept_handshake(output: &mut Write, handshake: &Handshake) -> io::Result<(u8, u8)> {
+    let version = handshake.version.min(PROTOCOL_VERSION);
+    let capabilities = handshake.capabilities & CAPABILITIES;
+    let mut reply = MAGIC.to_vec();
+    reply.push(version);
+    reply.push(capabilities);
+    try!(output.write_all(&reply));
+    Ok((version, capabilities))
+}
+
+// Returns the version the server accepted and the capabilities it granted.
+pub fn read_reply(input: &mut Read) -> io::Result<(u8, u8)> {
+    let mut reply = [0; 6];
+    try!(input.read_exact(&mut reply));
+    if reply[..4] != MAGIC {
+        return Err(invalid("Not a handshake reply."));
+    }
+    Ok((reply[4], reply[5]))
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+
+#[test]
+fn round_trip() {
+    let handshake = Handshake::new(1, CAPABILITY_KILLS, "Hunter Bot".to_string());
+    let bytes = serialize_handshake(&handshake);
+    assert_eq!(bytes[4..8], [1, 4, 10, b'H']);
+    assert_eq!(read_handshake(&mut Cursor::new(bytes)).unwrap(), handshake);
+    
+    let long = Handshake::new(1, 0, "é".repeat(200));
+    let bytes = serialize_handshake(&long);
+    assert_eq!(bytes[6], 254);
+    assert_eq!(read_handshake(&mut Cursor::new(bytes)).unwrap().name, "é".repeat(127));
+}
+
+#[test]
+fn invalid_handshakes() {
+    let read = |bytes: Vec<u8>| {
+        read_handshake(&mut Cursor::new(bytes)).map_err(|error| error.kind())
+    };
+    
+    assert_eq!(read(vec![1, 0, 0, 2, 1]), Err(io::ErrorKind::InvalidData));
+    assert_eq!(read(vec![0xB0, 0x75, 0x5B, 0x07, 0, 0, 0]), Err(io::ErrorKind::InvalidData));
+    assert_eq!(read(vec![0xB0, 0x75, 0x5B, 0x07, 1, 0, 2, 0xC3, 0x28]),
+               Err(io::ErrorKind::InvalidData));
+    assert_eq!(read(vec![0xB0, 0x75, 0x5B, 0x07, 1, 0, 5, b'a']),
+               Err(io::ErrorKind::UnexpectedEof));
+}
+
+#[test]
+fn accepting() {
+    let mut output = vec![];
+    let accepted = accept_handshake(&mut output, &Handshake::new(7, 0xFF, String::new())).unwrap();
+    assert_eq!(accepted, (PROTOCOL_VERSION, CAPABILITIES));
+    assert_eq!(read_reply(&mut Cursor::new(output)).unwrap(), (PROTOCOL_VERSION, CAPABILITIES));
+    
+    let mut output = vec![];
+    let scanning = Handshake::new(1, CAPABILITY_SCAN, String::new());
+    assert_eq!(accept_handshake(&mut output, &scanning).unwrap(), (1, CAPABILITY_SCAN));
+    assert_eq!(read_reply(&mut Cursor::new(output)).unwrap(), (1, CAPABILITY_SCAN));
+}
@@ -0,0 +1,226 @@
+// The JSON protocol, for bots running in a browser, which connect through a WebSocket (see
+// websocket.rs). Each message is one JSON object. Commands look like {"command": "look", "dx": -1,
+// "dy": 1}, {"command": "move", "direction": "north"}, {"command": "drill", "direction": "east"},
+// {"command": "wait", "ticks": 3} and {"command": "scan", "radius": 2}. Notifications use the same
+// words as the text protocol, as in {"notification": "your turn"}, {"notification": "see",
+// "appearance": "bot"}, {"notification": "placed", "place": 2, "participants": 5} and
+// {"notification": "scanned", "radius": 1, "appearances": ["floor", ...]}. Commands the server
+// doesn't understand are answered with {"notification": "error"}, as in the text protocol.
+
+use std::char;
+use std::iter::Peekable;
+use std::str::Chars;
+use notification::*;
+use text::*;
+
+
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Text(String),
+    Number(i64),
+    // true, false, null and numbers that aren't whole
+    Other,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+    
+    // Skips whitespace and takes the next character.
+    fn next(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.next()
+    }
+    
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().cloned()
+    }
+    
+    fn string(&mut self) -> Option<String> {
+        if self.next() != Some('"') {
+            return None;
+        }
+        let mut result = String::new();
+        loop {
+            let c = match self.chars.next() {
+                None => return None,
+                Some('"') => return Some(result),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let hex = self.chars.by_ref().take(4).collect::<String>();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                            None => return None,
+                            Some(c) => c,
+                        }
+                    }
+                    Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => c,
+                    _ => return None,
+                },
+                Some(c) => c,
+            };
+            result.push(c);
+        }
+    }
+    
+    fn value(&mut self) -> Option<Value> {
+        if self.peek() == Some('"') {
+            return self.string().map(Value::Text);
+        }
+        let mut word = String::new();
+        while self.chars.peek().map_or(false, |c| c.is_alphanumeric() || "+-.".contains(*c)) {
+            word.extend(self.chars.next());
+        }
+        match &word[..] {
+            "true" | "false" | "null" => Some(Value::Other),
+            _ => match word.parse::<i64>() {
+                Ok(number) => Some(Value::Number(number)),
+                Err(..) => word.parse::<f64>().ok().map(|_| Value::Other),
+            },
+        }
+    }
+    
+    fn object(&mut self) -> Option<Vec<(String, Value)>> {
+        let mut members = vec![];
+        if self.next() != Some('{') {
+            return None;
+        }
+        if self.peek() == Some('}') {
+            self.next();
+            return Some(members);
+        }
+        loop {
+            let name = self.string();
+            if self.next() != Some(':') {
+                return None;
+            }
+            match (name, self.value()) {
+                (Some(name), Some(value)) => members.push((name, value)),
+                _ => return None,
+            }
+            match self.next() {
+                Some(',') => (),
+                Some('}') => return Some(members),
+                _ => return None,
+            }
+        }
+    }
+}
+
+// Reads a JSON object whose values are all strings, numbers, booleans or null, which is all a
+// command needs. None if the text is anything else.
+fn parse_object(text: &str) -> Option<Vec<(String, Value)>> {
+    let mut parser = Parser { chars: text.chars().peekable() };
+    let members = parser.object();
+    match parser.next() {
+        None => members,
+        Some(_) => None,
+    }
+}
+
+// The binary message for a JSON command, so that every protocol is checked the same way. None if
+// the message isn't shaped like a command.
+pub fn json_command_message(message: &[u8]) -> Option<Vec<u8>> {
+    let members = match parse_object(&String::from_utf8_lossy(message)) {
+        None => return None,
+        Some(members) => members,
+    };
+    let member = |name: &str| {
+        members.iter().find(|member| member.0 == name).map(|member| member.1.clone())
+    };
+    let text = |name: &str| match member(name) {
+        Some(Value::Text(text)) => Some(text),
+        _ => None,
+    };
+    let number = |name: &str, min: i64, max: i64| match member(name) {
+        Some(Value::Number(number)) if number >= min && number <= max => Some(number as u8),
+        _ => None,
+    };
+    let offset = |name: &str| number(name, -128, 127);
+    let count = |name: &str| number(name, 0, 255);
+    let direction = || text("direction").and_then(|word| direction_code(&word));
+    
+    match text("command").as_ref().map(|command| &command[..]) {
+        Some("look") => offset("dx").and_then(|dx| offset("dy").map(|dy| vec![1, dx, dy])),
+        Some("move") => direction().map(|code| vec![2, code]),
+        Some("drill") => direction().map(|code| vec![3, code]),
+        Some("wait") => count("ticks").map(|ticks| vec![4, ticks]),
+        Some("scan") => count("radius").map(|radius| vec![5, radius]),
+        _ => None,
+    }
+}
+
+
+pub fn notification_json(notification: Notification) -> String {
+    let json = match notification {
+        Notification::YouSee(appearance) => {
+            format!("{{\"notification\":\"see\",\"appearance\":\"{}\"}}",
+                    appearance_name(appearance))
+        }
+        Notification::Placement { place, participants } => {
+            format!("{{\"notification\":\"placed\",\"place\":{},\"participants\":{}}}",
+                    place, participants)
+        }
+        Notification::YouScan { radius, appearances } => {
+            let appearances = appearances.into_iter().map(|appearance| {
+                format!("\"{}\"", appearance_name(appearance))
+            }).collect::<Vec<_>>();
+            format!("{{\"notification\":\"scanned\",\"radius\":{},\"appearances\":[{}]}}",
+                    radius, appearances.join(","))
+        }
+        notification => {
+            format!("{{\"notification\":\"{}\"}}", notification_line(notification).trim())
+        }
+    };
+    json + "\n"
+}
+
+
+#[test]
+fn commands() {
+    assert_eq!(json_command_message(b"{\"command\":\"look\",\"dx\":-1,\"dy\":1}\n"),
+               Some(vec![1, 255, 1]));
+    assert_eq!(json_command_message(b" { \"direction\" : \"north\", \"command\" : \"move\" } "),
+               Some(vec![2, 0]));
+    assert_eq!(json_command_message(b"{\"command\":\"drill\",\"direction\":\"east\",\"x\":null}"),
+               Some(vec![3, 1]));
+    assert_eq!(json_command_message(b"{\"command\":\"wait\",\"ticks\":3}"), Some(vec![4, 3]));
+    assert_eq!(json_command_message(b"{\"comm\\u0061nd\":\"scan\",\"radius\":2}"),
+               Some(vec![5, 2]));
+    
+    assert_eq!(json_command_message(b"{\"command\":\"look\",\"dx\":200,\"dy\":0}"), None);
+    assert_eq!(json_command_message(b"{\"command\":\"wait\",\"ticks\":1.5}"), None);
+    assert_eq!(json_command_message(b"{\"command\":\"move\",\"direction\":\"up\"}"), None);
+    assert_eq!(json_command_message(b"{\"command\":\"move\",\"direction\":\"north\"} x"), None);
+    assert_eq!(json_command_message(b"{\"command\":[\"wait\"]}"), None);
+    assert_eq!(json_command_message(b"move north"), None);
+    assert_eq!(json_command_message(b"{}"), None);
+}
+
+#[cfg(test)]
+use appearance::*;
+
+#[test]
+fn notifications() {
+    assert_eq!(notification_json(Notification::YourTurn), "{\"notification\":\"your turn\"}\n");
+    assert_eq!(notification_json(Notification::YouSee(Appearance::Ally)),
+               "{\"notification\":\"see\",\"appearance\":\"ally\"}\n");
+    assert_eq!(notification_json(Notification::Placement { place: 2, participants: 5 }),
+               "{\"notification\":\"placed\",\"place\":2,\"participants\":5}\n");
+    assert_eq!(notification_json(Notification::YouScan {
+                   radius: 1,
+                   appearances: vec![Appearance::Floor, Appearance::Bot],
+               }),
+               "{\"notification\":\"scanned\",\"radius\":1,\"appearances\":[\"floor\",\"bot\"]}\n");
+}
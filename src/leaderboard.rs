@@ -0,0 +1,311 @@
+// Elo-style ratings for every bot identity that has played a round. A round is scored as a set of
+// head-to-head games between every pair of participants, decided by which of the two placed better.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write, ErrorKind};
+use std::path::{Path, PathBuf};
+
+
+const INITIAL_RATING: f64 = 1500.0;
+const K_FACTOR: f64 = 32.0;
+// The first line of a saved leaderboard. Files saved before kills were counted have none.
+const HEADER: &'static str = "squish_the_bots leaderboard 2";
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rating {
+    pub rating: f64,
+    pub rounds: u32,
+    pub wins: u32,
+    // Other bots destroyed by this one
+    pub kills: u32,
+}
+impl Rating {
+    fn new() -> Rating {
+        Rating {
+            rating: INITIAL_RATING,
+            rounds: 0,
+            wins: 0,
+            kills: 0,
+        }
+    }
+}
+
+
+pub struct Leaderboard {
+    ratings: HashMap<String, Rating>,
+    path: Option<PathBuf>,
+}
+impl Leaderboard {
+    pub fn new() -> Leaderboard {
+        Leaderboard {
+            ratings: HashMap::new(),
+            path: None,
+        }
+    }
+    
+    // Reads the ratings saved at the given path, if any, and saves to it from then on.
+    pub fn open(path: &Path) -> io::Result<Leaderboard> {
+        let mut result = Leaderboard::new();
+        result.path = Some(path.to_path_buf());
+        
+        let mut text = String::new();
+        match File::open(path) {
+            Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(result),
+            Err(error) => return Err(error),
+            Ok(mut file) => { try!(file.read_to_string(&mut text)); },
+        }
+        
+        let mut lines = text.lines().enumerate().peekable();
+        let legacy = lines.peek().map(|&(_, line)| line) != Some(HEADER);
+        if !legacy {
+            lines.next();
+        }
+        
+        for (index, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            // files saved before kills were counted have one less field
+            let fields = if legacy {
+                let mut fields = line.splitn(4, '\t').collect::<Vec<_>>();
+                if fields.len() == 4 {
+                    fields.insert(3, "0");
+                }
+                fields
+            }
+            else {
+                line.splitn(5, '\t').collect::<Vec<_>>()
+            };
+            let parsed = match (fields.get(0), fields.get(1), fields.get(2), fields.get(3),
+                                fields.get(4)) {
+                (Some(rating), Some(rounds), Some(wins), Some(kills), Some(name)) => {
+                    match (rating.parse(), rounds.parse(), wins.parse(), kills.parse()) {
+                        // NaN and infinity would leave nothing to rank by
+                        (Ok(rating), Ok(rounds), Ok(wins), Ok(kills)) if f64::is_finite(rating) => {
+                            Some((*name, Rating {
+                                rating: rating,
+                                rounds: rounds,
+                                wins: wins,
+                                kills: kills,
+                            }))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match parsed {
+                None => return Err(io::Error::new(ErrorKind::InvalidData,
+                                                  format!("{}: malformed line {}",
+                                                          path.display(), index + 1))),
+                Some((name, rating)) => { result.ratings.insert(name.to_string(), rating); },
+            }
+        }
+        
+        Ok(result)
+    }
+    
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            None => return Ok(()),
+            Some(ref path) => path,
+        };
+        
+        let mut text = format!("{}\n", HEADER);
+        for (name, rating) in self.standings() {
+            text.push_str(&format!("{}\t{}\t{}\t{}\t{}\n", rating.rating, rating.rounds,
+                                   rating.wins, rating.kills, name.replace('\n', " ")));
+        }
+        
+        // written beside the old file first so that a crash can't leave it half written
+        let temporary = path.with_extension("tmp");
+        try!(try!(File::create(&temporary)).write_all(text.as_bytes()));
+        fs::rename(&temporary, path)
+    }
+    
+    pub fn rating(&self, name: &str) -> Option<Rating> {
+        self.ratings.get(name).map(Clone::clone)
+    }
+    
+    // Takes each participant's name and place in the round. Participants sharing a name count as
+    // one bot that played the round once, rated by the average of their results.
+    pub fn record_round(&mut self, placements: &[(String, usize)]) {
+        if placements.len() < 2 {
+            return;
+        }
+        
+        let before = placements.iter()
+                               .map(|&(ref name, _)| self.rating(name).unwrap_or(Rating::new()))
+                               .collect::<Vec<_>>();
+        let mut counted = HashSet::new();
+        
+        for (index, &(ref name, place)) in placements.iter().enumerate() {
+            let namesakes = placements.iter().filter(|entry| entry.0 == *name).count();
+            let opponents = placements.len() - namesakes;
+            if opponents == 0 {
+                continue;
+            }
+            let weight = (opponents * namesakes) as f64;
+            
+            let mut change = 0.0;
+            for (other, &(ref other_name, other_place)) in placements.iter().enumerate() {
+                if other == index || other_name == name {
+                    continue;
+                }
+                let expected = 1.0 / (1.0 + 10f64.powf((before[other].rating -
+                                                        before[index].rating) / 400.0));
+                let actual = if place < other_place {
+                    1.0
+                }
+                else if place == other_place {
+                    0.5
+                }
+                else {
+                    0.0
+                };
+                change += K_FACTOR * (actual - expected) / weight;
+            }
+            
+            let rating = self.ratings.entry(name.clone()).or_insert(Rating::new());
+            rating.rating += change;
+            if counted.insert(name) {
+                rating.rounds += 1;
+                if placements.iter().any(|entry| entry.0 == *name && entry.1 == 1) {
+                    rating.wins += 1;
+                }
+            }
+        }
+    }
+    
+    // Takes the killer's name once for every kill in the round.
+    pub fn record_kills(&mut self, killers: &[String]) {
+        for name in killers {
+            self.ratings.entry(name.clone()).or_insert(Rating::new()).kills += 1;
+        }
+    }
+    
+    // Best rated first.
+    pub fn standings(&self) -> Vec<(String, Rating)> {
+        let mut result = self.ratings.iter()
+                                     .map(|(name, rating)| (name.clone(), *rating))
+                                     .collect::<Vec<_>>();
+        result.sort_by(|a, b| {
+            b.1.rating.total_cmp(&a.1.rating).then_with(|| a.0.cmp(&b.0))
+        });
+        result
+    }
+    
+    pub fn to_html(&self) -> String {
+        let standings = self.standings();
+        if standings.is_empty() {
+            return "No rounds have been played yet.".to_string();
+        }
+        
+        let mut result = "<table>\n<tr><th>Bot</th><th>Rating</th><th>Rounds</th><th>Wins</th>\
+                          <th>Kills</th></tr>\n".to_string();
+        for (name, rating) in standings {
+            let name = name.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+            result.push_str(&format!("<tr><td>{}</td><td>{:.0}</td><td>{}</td><td>{}</td>\
+                                      <td>{}</td></tr>\n",
+                                     name, rating.rating, rating.rounds, rating.wins,
+                                     rating.kills));
+        }
+        result.push_str("</table>");
+        result
+    }
+}
+
+
+#[cfg(test)]
+use std::env;
+
+#[test]
+fn rating_changes() {
+    let mut leaderboard = Leaderboard::new();
+    leaderboard.record_round(&[("a".to_string(), 1), ("b".to_string(), 2)]);
+    
+    let a = leaderboard.rating("a").unwrap();
+    let b = leaderboard.rating("b").unwrap();
+    assert_eq!(a.rating, INITIAL_RATING + K_FACTOR / 2.0);
+    assert_eq!(b.rating, INITIAL_RATING - K_FACTOR / 2.0);
+    assert_eq!((a.rounds, a.wins), (1, 1));
+    assert_eq!((b.rounds, b.wins), (1, 0));
+    
+    leaderboard.record_round(&[("a".to_string(), 1), ("b".to_string(), 1),
+                               ("c".to_string(), 3)]);
+    let a2 = leaderboard.rating("a").unwrap();
+    let b2 = leaderboard.rating("b").unwrap();
+    let c = leaderboard.rating("c").unwrap();
+    assert!(a2.rating > a.rating);
+    assert!(b2.rating > b.rating);
+    assert!(c.rating < INITIAL_RATING);
+    assert_eq!(leaderboard.standings().iter().map(|entry| &*entry.0).collect::<Vec<_>>(),
+               vec!["a", "b", "c"]);
+}
+
+#[test]
+fn shared_names() {
+    let mut leaderboard = Leaderboard::new();
+    leaderboard.record_round(&[("a".to_string(), 1), ("a".to_string(), 3), ("b".to_string(), 2)]);
+    
+    let a = leaderboard.rating("a").unwrap();
+    let b = leaderboard.rating("b").unwrap();
+    assert_eq!((a.rounds, a.wins), (1, 1));
+    assert_eq!((b.rounds, b.wins), (1, 0));
+    // a won one game against b and lost the other
+    assert_eq!(a.rating, INITIAL_RATING);
+    assert_eq!(b.rating, INITIAL_RATING);
+    
+    leaderboard.record_round(&[("a".to_string(), 1), ("a".to_string(), 2)]);
+    assert_eq!(leaderboard.rating("a"), Some(a));
+}
+
+#[test]
+fn single_participant_is_not_rated() {
+    let mut leaderboard = Leaderboard::new();
+    leaderboard.record_round(&[("a".to_string(), 1)]);
+    assert_eq!(leaderboard.rating("a"), None);
+}
+
+#[test]
+fn persistence() {
+    let path = env::temp_dir().join(format!("squish_the_bots_leaderboard_{}.txt",
+                                            ::std::process::id()));
+    drop(fs::remove_file(&path));
+    
+    {
+        let mut leaderboard = Leaderboard::open(&path).unwrap();
+        assert!(leaderboard.standings().is_empty());
+        leaderboard.record_round(&[("tab\tbot".to_string(), 2), ("other".to_string(), 1)]);
+        leaderboard.save().unwrap();
+    }
+    
+    let leaderboard = Leaderboard::open(&path).unwrap();
+    assert_eq!(leaderboard.rating("other").unwrap().wins, 1);
+    assert_eq!(leaderboard.rating("tab\tbot").unwrap().rounds, 1);
+    
+    // saved before kills were counted
+    let mut file = File::create(&path).unwrap();
+    file.write_all(b"1516\t1\t1\tother\n1484\t1\t0\ttab\tbot\n1500\t3\t1\t7\tbot\n").unwrap();
+    let mut leaderboard = Leaderboard::open(&path).unwrap();
+    assert_eq!(leaderboard.rating("tab\tbot").unwrap().rounds, 1);
+    assert_eq!(leaderboard.rating("other").unwrap().kills, 0);
+    assert_eq!(leaderboard.rating("7\tbot").unwrap().rounds, 3);
+    assert_eq!(leaderboard.rating("bot"), None);
+    
+    leaderboard.record_kills(&["other".to_string(), "other".to_string()]);
+    leaderboard.save().unwrap();
+    let leaderboard = Leaderboard::open(&path).unwrap();
+    assert_eq!(leaderboard.rating("other").unwrap().kills, 2);
+    assert_eq!(leaderboard.rating("tab\tbot").unwrap().kills, 0);
+    assert_eq!(leaderboard.rating("7\tbot").unwrap().kills, 0);
+    
+    let mut file = File::create(&path).unwrap();
+    file.write_all(format!("{}\nNaN\t1\t0\t0\tbot\n", HEADER).as_bytes()).unwrap();
+    assert_eq!(Leaderboard::open(&path).err().map(|error| error.kind()),
+               Some(ErrorKind::InvalidData));
+    
+    fs::remove_file(&path).unwrap();
+}
@@ -1 +1,39 @@
+#![deny(unused_must_use)]
+
+extern crate rand;
+
+#[macro_use]
+mod macros;
+
+pub mod action;
+pub mod appearance;
+pub mod area;
+pub mod cooldown;
+pub mod entity;
+pub mod event;
+pub mod generator;
+pub mod handshake;
+pub mod json;
+pub mod leaderboard;
+pub mod lobby;
+pub mod mapfile;
+pub mod network;
+pub mod notification;
+pub mod pipe;
+pub mod positioned;
+pub mod protocol;
+pub mod pushable;
+pub mod replay;
+pub mod resolution;
+pub mod session;
+pub mod space;
+pub mod team;
+pub mod terrain;
+pub mod text;
+pub mod tournament;
+pub mod vector;
+pub mod vision;
+pub mod websocket;
+
+mod tests;
 pub mod example_bots;
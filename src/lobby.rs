@@ -1,96 +1,318 @@
 use std::io::{self, Read, Write, Cursor};
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::*;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use entity::*;
 use area::*;
 use notification::*;
 use session::*;
+use generator::*;
+use leaderboard::*;
+use replay::*;
+use team::*;
+use protocol::*;
+use handshake::*;
 
 
 pub struct Participant {
-    input: Box<Read>,
-    output: Box<Write>,
+    pub input: Box<Read>,
+    pub output: Box<Write>,
+    // Identifies the bot on the leaderboard
+    pub name: String,
+    pub team: Option<Team>,
+    // How long the bot has to send each command. Waits forever when not set.
+    pub timeout: Option<Duration>,
+    // Binary unless the bot asked for another protocol when it shook hands.
+    pub protocol: Protocol,
+    // The optional parts of the protocol the bot can be sent, as CAPABILITY_ flags. All of them
+    // unless the bot connected over the network without asking for them.
+    pub capabilities: u8,
 }
 impl Participant {
-    pub fn new(input: Box<Read>, output: Box<Write>) -> Participant {
+    pub fn new(input: Box<Read>, output: Box<Write>, name: String) -> Participant {
         Participant {
             input: input,
             output: output,
+            name: name,
+            team: None,
+            timeout: None,
+            protocol: Protocol::Binary,
+            capabilities: CAPABILITIES,
         }
     }
-    pub fn new_boxed<R: 'static+Read+Sized, W: 'static+Write+Sized>(input: R, output: W)
+    pub fn new_boxed<R: 'static+Read+Sized, W: 'static+Write+Sized>(input: R, output: W,
+                                                                    name: String)
             -> Participant {
-        Self::new(Box::new(input), Box::new(output))
+        Self::new(Box::new(input), Box::new(output), name)
     }
 }
 unsafe impl Send for Participant {
 }
 
 
+#[derive(Clone)]
+pub struct LobbyConfig {
+    pub turn_delay: Duration,
+    // Every round uses this seed when set. Otherwise each round picks its own.
+    pub seed: Option<Seed>,
+    // Rounds rotate through these in order.
+    pub maps: Vec<Arc<MapGenerator>>,
+    pub rules: Rules,
+    // Participants are dealt out to this many teams at the start of every round when set. Fewer
+    // than two would leave every round over before it starts, so they are ignored.
+    pub teams: Option<Team>,
+    pub leaderboard: Arc<Mutex<Leaderboard>>,
+    // Every round is saved here when set.
+    pub replay_directory: Option<PathBuf>,
+    // How many rounds can be played at once, each in an arena of its own.
+    pub max_arenas: usize,
+    // The most participants a round can have. Everyone waiting plays in the same round when not
+    // set. Fewer than two could never start a round, so rounds always have room for two.
+    pub arena_size: Option<usize>,
+}
+impl LobbyConfig {
+    pub fn new(turn_delay: Duration) -> LobbyConfig {
+        LobbyConfig {
+            turn_delay: turn_delay,
+            seed: None,
+            maps: vec![Arc::new(Classic)],
+            rules: Rules::new(),
+            teams: None,
+            leaderboard: Arc::new(Mutex::new(Leaderboard::new())),
+            replay_directory: None,
+            max_arenas: 1,
+            arena_size: None,
+        }
+    }
+}
+
+
+enum Arrival {
+    Joined(Participant),
+    // An arena's round is over and these participants are still connected.
+    Finished(Vec<Participant>),
+    // The lobby's owner is gone.
+    Closed,
+}
+
+
+// Queues up participants and starts a round in a new arena whenever there are enough of them and
+// fewer arenas than the limit. When a round ends, its participants go back to the queue.
 pub struct Lobby {
-    sender: Sender<Participant>,
-    join_handle: JoinHandle<()>,
+    sender: Sender<Arrival>,
+    join_handle: Option<JoinHandle<()>>,
 }
 impl Lobby {
-    pub fn new(delay: Duration) -> io::Result<Lobby> {
+    pub fn new(config: LobbyConfig) -> io::Result<Lobby> {
         let (sender, receiver) = channel();
-        let join_handle = try!(start_lobby(receiver, delay));
+        let join_handle = try!(start_lobby(receiver, sender.clone(), config));
         
         Ok(Lobby {
             sender: sender,
-            join_handle: join_handle,
+            join_handle: Some(join_handle),
         })
     }
     
     pub fn add(&self, participant: Participant) -> Result<(), SendError<Participant>> {
-        self.sender.send(participant)
+        match self.sender.send(Arrival::Joined(participant)) {
+            Ok(()) => Ok(()),
+            Err(SendError(Arrival::Joined(participant))) => Err(SendError(participant)),
+            Err(_) => {
+                debug_unreachable!();
+                Ok(())
+            }
+        }
     }
     
-    fn stop(self) -> JoinHandle<()> {
-        self.join_handle
+    fn stop(mut self) -> JoinHandle<()> {
+        self.join_handle.take().unwrap()
+        // implicitly calls #drop() to stop the lobby once its arenas are done
+    }
+}
+impl Drop for Lobby {
+    fn drop(&mut self) {
+        // the lobby may have ended already
+        drop(self.sender.send(Arrival::Closed));
     }
 }
 
 
-fn start_lobby(receiver: Receiver<Participant>, delay: Duration) -> io::Result<JoinHandle<()>> {
-    thread::Builder::new().name("Instance Runner".to_string()).spawn(move|| {
-        let mut participants = vec![];
-        
+// What the lobby thread keeps track of between arrivals.
+struct Queue {
+    waiting: Vec<Participant>,
+    // How many at the front of the queue have been sent NewRound for a round that didn't start
+    announced: usize,
+    arenas: usize,
+    closed: bool,
+}
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            waiting: vec![],
+            announced: 0,
+            arenas: 0,
+            closed: false,
+        }
+    }
+    
+    // Waits for the next arrival, then takes in everyone else who's already here so that they all
+    // get a place in the next round. False once nobody can send anything anymore.
+    fn receive(&mut self, receiver: &Receiver<Arrival>) -> bool {
+        let mut arrival = match receiver.recv() {
+            Ok(arrival) => arrival,
+            Err(RecvError) => return false,
+        };
         loop {
-            while participants.len() < 2 {
-                match receiver.recv() {
-                    Ok(participant) => participants.push((participant.input, participant.output)),
-                    Err(RecvError) => return,
+            match arrival {
+                Arrival::Joined(participant) => self.waiting.push(participant),
+                Arrival::Finished(participants) => {
+                    self.arenas -= 1;
+                    self.waiting.extend(participants);
                 }
+                Arrival::Closed => self.closed = true,
             }
-            
-            loop {
-                match receiver.try_recv() {
-                    Ok(participant) => participants.push((participant.input, participant.output)),
-                    Err(TryRecvError::Empty) => break,
-                    Err(TryRecvError::Disconnected) => return,
-                }
+            arrival = match receiver.try_recv() {
+                Ok(arrival) => arrival,
+                Err(_) => return true,
+            };
+        }
+    }
+    
+    // Whether the lobby has nothing left to do, either because its owner is gone or because
+    // everyone disconnected.
+    fn over(&self) -> bool {
+        self.arenas == 0 && (self.closed || self.waiting.is_empty())
+    }
+    
+    // The participants for the next round, who have all been sent NewRound, or None if too few of
+    // them are still connected.
+    fn next_round(&mut self, size: Option<usize>) -> Option<Vec<Participant>> {
+        let count = size.map_or(self.waiting.len(), |size| size.max(2).min(self.waiting.len()));
+        let mut participants = vec![];
+        for (index, mut participant) in self.waiting.drain(..count).enumerate() {
+            if index < self.announced {
+                participants.push(participant);
+                continue;
             }
-            
-            let elements = participants.drain(..).collect::<Vec<(Box<Read>, Box<Write>)>>();
-            for (mut input, mut output) in elements {
-                let notify_result = notify(&mut output, Notification::NewRound);
-                match notify_result {
-                    Ok(()) => participants.push((input, output)),
-                    Err(error) => println!("Participant disconnected - {:?}", error),
-                }
+            let notify_result = notify(&mut participant.output, Notification::NewRound,
+                                       participant.protocol);
+            match notify_result {
+                Ok(()) => participants.push(participant),
+                Err(error) => println!("{} disconnected - {:?}", participant.name, error),
+            }
+        }
+        
+        if participants.len() < 2 {
+            // back to the front of the queue, not to be told about the round again
+            self.announced = participants.len();
+            participants.append(&mut self.waiting);
+            self.waiting = participants;
+            return None;
+        }
+        self.announced = self.announced.saturating_sub(count);
+        Some(participants)
+    }
+}
+
+fn start_lobby(receiver: Receiver<Arrival>, sender: Sender<Arrival>, config: LobbyConfig)
+        -> io::Result<JoinHandle<()>> {
+    thread::Builder::new().name("Lobby".to_string()).spawn(move|| {
+        let mut queue = Queue::new();
+        let mut rounds_played = 0;
+        
+        while queue.receive(&receiver) {
+            // only once every arrival so far is in, so that nobody who joined is dropped
+            if queue.over() {
+                return;
+            }
+            if queue.closed {
+                continue;
             }
             
-            match participants.len() {
-                0 => return,
-                1 => continue,
-                _ => execute_round(&mut participants, delay),
+            while queue.arenas < config.max_arenas && queue.waiting.len() >= 2 {
+                let participants = match queue.next_round(config.arena_size) {
+                    None => continue,
+                    Some(participants) => participants,
+                };
+                match start_arena(participants, &config, rounds_played, sender.clone()) {
+                    Ok(()) => {
+                        queue.arenas += 1;
+                        rounds_played += 1;
+                    }
+                    Err(error) => {
+                        println!("Could not start arena - {:?}", error);
+                        break;
+                    }
+                }
             }
         }
     })
 }
 
+// Plays one round on a thread of its own and sends back whoever is still connected at the end.
+fn start_arena(mut participants: Vec<Participant>, config: &LobbyConfig, rounds_played: usize,
+               sender: Sender<Arrival>)
+        -> io::Result<()> {
+    let config = config.clone();
+    let name = format!("Arena {}", rounds_played);
+    try!(thread::Builder::new().name(name).spawn(move|| {
+        let seed = config.seed.unwrap_or_else(random_seed);
+        let generator = next_map(&config.maps, rounds_played, participants.len());
+        println!("Starting round on {} map with seed {}", generator.name(), seed);
+        if let Some(teams) = config.teams.filter(|teams| *teams >= 2) {
+            let assigned = assign_teams(participants.len(), teams);
+            for (participant, team) in participants.iter_mut().zip(assigned) {
+                participant.team = team;
+            }
+        }
+        
+        let replay = execute_round(&mut participants, &*generator, &config.rules,
+                                   config.turn_delay, seed);
+        if let Some(ref directory) = config.replay_directory {
+            save_replay(&replay, directory, rounds_played);
+        }
+        
+        match config.leaderboard.lock() {
+            Err(_) => (), // another thread panicked while holding the lock
+            Ok(mut leaderboard) => {
+                leaderboard.record_round(&replay.placements);
+                leaderboard.record_kills(&replay.killers());
+                if let Err(error) = leaderboard.save() {
+                    println!("Could not save leaderboard - {:?}", error);
+                }
+            }
+        }
+        
+        // the lobby may have ended already
+        drop(sender.send(Arrival::Finished(participants)));
+    }));
+    Ok(())
+}
+
+// Named so that rounds played at the same time with the same seed don't overwrite each other.
+fn save_replay(replay: &Replay, directory: &Path, round: usize) {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs())
+                                   .unwrap_or(0);
+    let path = directory.join(format!("{}-{}-{}.replay", started, round, replay.seed));
+    if let Err(error) = replay.save(&path) {
+        println!("Could not save replay - {:?}", error);
+    }
+}
+
+// The next map in the rotation with room for everyone, or a generated one if none have room.
+pub fn next_map(maps: &[Arc<MapGenerator>], rounds_played: usize, participants: usize)
+        -> Arc<MapGenerator> {
+    for index in 0..maps.len() {
+        let map = &maps[(rounds_played + index) % maps.len()];
+        if map.max_participants().map_or(true, |max| participants <= max) {
+            return map.clone();
+        }
+    }
+    Arc::new(Classic)
+}
+
 #[cfg(test)]
 use tests::{SharedWrite};
 #[cfg(test)]
@@ -100,13 +322,13 @@ use std::rc::Rc;
 
 #[test]
 fn terminates_when_dropped() {
-    let lobby = Lobby::new(Duration::from_millis(0)).unwrap();
+    let lobby = Lobby::new(LobbyConfig::new(Duration::from_millis(0))).unwrap();
     lobby.stop().join().unwrap();
 }
 
 #[test]
 fn waits_for_2_participants() {
-    let lobby = Lobby::new(Duration::from_millis(0)).unwrap();
+    let lobby = Lobby::new(LobbyConfig::new(Duration::from_millis(0))).unwrap();
     
     let output_a = Rc::new(RefCell::new(Vec::<u8>::new()));
     let output_b = Rc::new(RefCell::new(Vec::<u8>::new()));
@@ -114,8 +336,8 @@ fn waits_for_2_participants() {
     let shared_a = SharedWrite::new(output_a.clone());
     let shared_b = SharedWrite::new(output_b.clone());
     
-    let a = Participant::new_boxed(Cursor::new(vec![]), shared_a);
-    let b = Participant::new_boxed(Cursor::new(vec![]), shared_b);
+    let a = Participant::new_boxed(Cursor::new(vec![]), shared_a, "a".to_string());
+    let b = Participant::new_boxed(Cursor::new(vec![]), shared_b, "b".to_string());
     
     lobby.add(a).unwrap();
     
@@ -136,17 +358,17 @@ fn waits_for_2_participants() {
 
 #[test]
 fn waits_after_disconnection() {
-    let lobby = Lobby::new(Duration::from_millis(0)).unwrap();
+    let lobby = Lobby::new(LobbyConfig::new(Duration::from_millis(0))).unwrap();
     
     let output_a = Rc::new(RefCell::new(Vec::<u8>::new()));
     let mut shared_a = SharedWrite::new(output_a.clone());
     drop(output_a);
     shared_a.close();
-    let a = Participant::new_boxed(Cursor::new(vec![]), shared_a);
+    let a = Participant::new_boxed(Cursor::new(vec![]), shared_a, "a".to_string());
     
     let output_b = Rc::new(RefCell::new(Vec::<u8>::new()));
     let shared_b = SharedWrite::new(output_b.clone());
-    let b = Participant::new_boxed(Cursor::new(vec![]), shared_b);
+    let b = Participant::new_boxed(Cursor::new(vec![]), shared_b, "b".to_string());
     
     lobby.add(a).unwrap();
     
@@ -160,5 +382,106 @@ fn waits_after_disconnection() {
     
     assert_eq!(*output_b.borrow(), vec![5]);
     
+    let output_c = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let shared_c = SharedWrite::new(output_c.clone());
+    lobby.add(Participant::new_boxed(Cursor::new(vec![]), shared_c, "c".to_string())).unwrap();
+    
+    thread::sleep(Duration::from_millis(100));
+    
+    // b isn't told about the round a second time
+    assert_eq!(*output_b.borrow(), vec![5, 1]);
+    assert_eq!(*output_c.borrow(), vec![5, 1]);
+    
+    lobby.stop().join().unwrap();
+}
+
+#[test]
+fn joined_after_last_arena() {
+    let (sender, receiver) = channel();
+    let mut queue = Queue::new();
+    queue.arenas = 1;
+    
+    // everyone in the last round disconnected, just as someone else joined
+    sender.send(Arrival::Finished(vec![])).unwrap();
+    let a = Participant::new_boxed(Cursor::new(vec![]), vec![], "a".to_string());
+    sender.send(Arrival::Joined(a)).unwrap();
+    
+    assert!(queue.receive(&receiver));
+    assert_eq!(queue.waiting.len(), 1);
+    assert!(!queue.over());
+}
+
+#[test]
+fn arenas_hold_at_least_2() {
+    let mut config = LobbyConfig::new(Duration::from_millis(0));
+    config.arena_size = Some(1);
+    let lobby = Lobby::new(config).unwrap();
+    
+    let output_a = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let output_b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let shared_a = SharedWrite::new(output_a.clone());
+    let shared_b = SharedWrite::new(output_b.clone());
+    lobby.add(Participant::new_boxed(Cursor::new(vec![]), shared_a, "a".to_string())).unwrap();
+    lobby.add(Participant::new_boxed(Cursor::new(vec![]), shared_b, "b".to_string())).unwrap();
+    
+    thread::sleep(Duration::from_millis(100));
+    
+    assert_eq!(*output_a.borrow(), vec![5, 1]);
+    assert_eq!(*output_b.borrow(), vec![5, 1]);
+    
+    lobby.stop().join().unwrap();
+}
+
+#[cfg(test)]
+use pipe::*;
+
+#[cfg(test)]
+fn available(reader: &mut PipeReader) -> Vec<u8> {
+    let mut bytes = vec![];
+    drop(reader.read_to_end(&mut bytes));
+    bytes
+}
+
+#[test]
+fn concurrent_arenas() {
+    let mut config = LobbyConfig::new(Duration::from_millis(0));
+    config.max_arenas = 2;
+    config.arena_size = Some(2);
+    let lobby = Lobby::new(config).unwrap();
+    
+    let mut writers = vec![];
+    let mut readers = vec![];
+    for name in &["a", "b", "c", "d", "e", "f"] {
+        // the bots never answer, so each round waits until they hang up
+        let (input, writer) = pipe();
+        let (mut reader, output) = pipe();
+        reader.set_nonblocking(true);
+        writers.push(writer);
+        readers.push(reader);
+        lobby.add(Participant::new_boxed(input, output, name.to_string())).unwrap();
+        
+        thread::sleep(Duration::from_millis(20));
+    }
+    
+    thread::sleep(Duration::from_millis(100));
+    
+    for reader in &mut readers[..4] {
+        assert_eq!(available(reader), vec![5, 1]);
+    }
+    // there are already as many arenas as allowed
+    for reader in &mut readers[4..] {
+        assert_eq!(available(reader), vec![]);
+    }
+    
+    // hanging up ends the first round
+    writers.drain(..2);
+    
+    thread::sleep(Duration::from_millis(100));
+    
+    for reader in &mut readers[4..] {
+        assert_eq!(available(reader), vec![5, 1]);
+    }
+    
+    writers.clear();
     lobby.stop().join().unwrap();
 }
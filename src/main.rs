@@ -1,27 +1,7 @@
 #![deny(unused_must_use)]
 
-extern crate rand;
 extern crate getopts;
-
-#[macro_use]
-mod macros;
-
-mod action;
-mod appearance;
-mod area;
-mod cooldown;
-mod entity;
-mod lobby;
-mod network;
-mod notification;
-mod positioned;
-mod pushable;
-mod session;
-mod space;
-mod vector;
-
-mod tests;
-mod example_bots;
+extern crate squish_the_bots;
 
 
 use std::env;
@@ -30,7 +10,16 @@ use std::str::FromStr;
 use std::time::Duration;
 use std::fs::File;
 use std::io::Write;
-use network::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use squish_the_bots::network::*;
+use squish_the_bots::lobby::*;
+use squish_the_bots::session::*;
+use squish_the_bots::generator::*;
+use squish_the_bots::mapfile::*;
+use squish_the_bots::leaderboard::*;
+use squish_the_bots::resolution::*;
 use getopts::Options;
 
 
@@ -44,6 +33,27 @@ fn main() {
     options.reqopt("s", "simulation", "Port to accept bot socket connections on", "PORT");
     options.optopt("e", "simulation-external",
                    "External port of simulation, if different than internal.", "PORT");
+    options.optopt("", "seed", "Seed every round with this value to reproduce it.", "SEED");
+    options.optopt("m", "maps",
+                   "Comma-separated map styles to rotate through: classic, maze, islands, open.",
+                   "STYLES");
+    options.optopt("", "map-directory", "Rotate through the .map files in this directory instead.",
+                   "DIR");
+    options.optopt("l", "leaderboard", "File to keep bot ratings in between restarts.", "FILE");
+    options.optopt("", "view-range", "Hide squares further than this many steps from each bot.",
+                   "SQUARES");
+    options.optflag("", "line-of-sight", "Hide squares that are behind bots and blocks.");
+    options.optopt("", "teams", "Split the participants in each round into this many teams.",
+                   "TEAMS");
+    options.optopt("", "initiative", "Who moves first each tick: fixed, rotating or random.",
+                   "POLICY");
+    options.optflag("", "simultaneous", "Carry out every bot's move at the same time each tick.");
+    options.optopt("", "tick-limit", "End each round in a draw after this many ticks.", "TICKS");
+    options.optopt("r", "replay-directory", "Save a replay of every round in this directory.",
+                   "DIR");
+    options.optopt("", "arenas", "Play up to this many rounds at once. Defaults to 1.", "ARENAS");
+    options.optopt("", "arena-size", "Put at most this many participants in each round.",
+                   "PARTICIPANTS");
     
     let matches = match options.parse(&args) {
         Ok(result) => result,
@@ -60,8 +70,67 @@ fn main() {
             Ok(address) => address,
         };
     
-    let simulation = single_lobby(sim_address, Duration::from_millis(2000),
-                                  Duration::from_millis(450)).unwrap();
+    let mut config = LobbyConfig::new(Duration::from_millis(450));
+    config.seed = matches.opt_str("seed").map(|seed| match seed.parse::<Seed>() {
+        Err(..) => panic!("Invalid seed."),
+        Ok(seed) => seed,
+    });
+    if let Some(styles) = matches.opt_str("maps") {
+        config.maps = styles.split(',').map(|style| match map_generator(style.trim()) {
+            None => panic!("Unknown map style \"{}\".", style),
+            Some(generator) => generator,
+        }).collect();
+    }
+    if let Some(directory) = matches.opt_str("map-directory") {
+        config.maps = match load_map_directory(Path::new(&directory)) {
+            Err((path, error)) => panic!("Could not load map {}: {}", path, error),
+            Ok(ref maps) if maps.is_empty() => panic!("No .map files in {}.", directory),
+            Ok(maps) => maps.into_iter().map(|map| Arc::new(map) as Arc<MapGenerator>).collect(),
+        };
+    }
+    if let Some(path) = matches.opt_str("leaderboard") {
+        config.leaderboard = match Leaderboard::open(Path::new(&path)) {
+            Err(error) => panic!("Could not load leaderboard: {}", error),
+            Ok(leaderboard) => Arc::new(Mutex::new(leaderboard)),
+        };
+    }
+    config.rules.view_range = matches.opt_str("view-range").map(|range| match range.parse() {
+        Err(..) => panic!("Invalid view range."),
+        Ok(range) => range,
+    });
+    config.rules.line_of_sight = matches.opt_present("line-of-sight");
+    if let Some(name) = matches.opt_str("initiative") {
+        config.rules.initiative = match initiative(&name) {
+            None => panic!("Unknown initiative \"{}\".", name),
+            Some(initiative) => initiative,
+        };
+    }
+    config.rules.simultaneous = matches.opt_present("simultaneous");
+    config.rules.tick_limit = matches.opt_str("tick-limit").map(|limit| match limit.parse() {
+        Err(..) => panic!("Invalid tick limit."),
+        Ok(limit) => limit,
+    });
+    config.teams = matches.opt_str("teams").map(|teams| match teams.parse() {
+        Err(..) => panic!("Invalid number of teams."),
+        Ok(teams) if teams < 2 => panic!("There must be at least two teams."),
+        Ok(teams) => teams,
+    });
+    config.replay_directory = matches.opt_str("replay-directory").map(PathBuf::from);
+    if let Some(arenas) = matches.opt_str("arenas") {
+        config.max_arenas = match arenas.parse() {
+            Err(..) => panic!("Invalid number of arenas."),
+            Ok(0) => panic!("There must be at least one arena."),
+            Ok(arenas) => arenas,
+        };
+    }
+    config.arena_size = matches.opt_str("arena-size").map(|size| match size.parse() {
+        Err(..) => panic!("Invalid arena size."),
+        Ok(size) if size < 2 => panic!("Arenas must fit at least two participants."),
+        Ok(size) => size,
+    });
+    let leaderboard = config.leaderboard.clone();
+    
+    let simulation = single_lobby(sim_address, Duration::from_millis(2000), config).unwrap();
     println!("Waiting for simulation socket connections on {}", simulation.addr);
     
     
@@ -69,18 +138,34 @@ fn main() {
                                .unwrap_or(simulation.addr.port().to_string());
     
     let static_dir = matches.opt_str("static-directory").unwrap();
-    write_statics(&static_dir, &external_port);
+    write_statics(&static_dir);
+    
+    // keeps the leaderboard on the index page current
+    thread::spawn(move|| {
+        loop {
+            let standings = match leaderboard.lock() {
+                Err(_) => return,
+                Ok(leaderboard) => leaderboard.to_html(),
+            };
+            write_index(&static_dir, &external_port, &standings);
+            
+            thread::sleep(Duration::from_secs(10));
+        }
+    });
     
     simulation.join().unwrap().unwrap();
 }
 
-fn write_statics(static_dir: &String, external_port: &str) {
+fn write_index(static_dir: &String, external_port: &str, standings: &str) {
     let index_page = include_str!("./index.html");
     let index_page = index_page.replace("#####", &external_port);
+    let index_page = index_page.replace("<!-- leaderboard -->", standings);
     
     let mut f = File::create(static_dir.to_string() + "/index.html").unwrap();
     f.write_all(index_page.as_bytes()).unwrap();
-    
+}
+
+fn write_statics(static_dir: &String) {
     let mut f = File::create(static_dir.to_string() + "/demo.gif").unwrap();
     f.write_all(include_bytes!("./demo.gif")).unwrap();
 }
@@ -0,0 +1,443 @@
+// Hand-authored arenas. A map file is a header of "key: value" lines, a "---" separator and then
+// one line of characters per row of the arena:
+//
+//     name: Crossroads
+//     size: 7x3
+//     players: 2
+//     ---
+//     ~~~.~~~
+//     1..#..2
+//     ~~~.~~~
+//
+// '.' is floor, '#' is a block, '~' is the abyss and the digits 0-9 are spawn slots. Slots are
+// filled lowest number first. The size and players lines are optional.
+//
+// The rest are special floors: '=' is ice, '^', '>', 'v' and '<' are conveyors running in the
+// direction they point, '%' is floor that crumbles after being crossed a few times and each
+// lowercase letter other than 'v', which is taken by the conveyor, is a teleporter leading to the
+// other square with the same letter.
+
+use rand::{Rng, StdRng};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use area::*;
+use space::*;
+use space::Direction::*;
+use generator::*;
+use terrain::*;
+
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+impl fmt::Display for MapError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Io(ref error) => write!(formatter, "{}", error),
+            MapError::Syntax { line, column, ref message } => {
+                write!(formatter, "line {}, column {}: {}", line, column, message)
+            }
+        }
+    }
+}
+
+fn syntax_error<R>(line: usize, column: usize, message: String) -> Result<R, MapError> {
+    Err(MapError::Syntax {
+        line: line,
+        column: column,
+        message: message,
+    })
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tile {
+    Floor,
+    Block,
+    Abyss,
+    Spawn(u8),
+    Ice,
+    Conveyor(Direction),
+    Crumbling,
+    Teleporter(char),
+}
+
+
+const CRUMBLING_CROSSINGS: u8 = 3;
+
+
+#[derive(Debug)]
+pub struct MapFile {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub max_players: usize,
+    pub rows: Vec<Vec<Tile>>,
+}
+impl MapFile {
+    fn spawn_slots(&self) -> Vec<(u8, Position)> {
+        let mut result = vec![];
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if let Tile::Spawn(slot) = *tile {
+                    result.push((slot, Position::new(x as i32, y as i32)));
+                }
+            }
+        }
+        result.sort_by_key(|&(slot, _)| slot);
+        result
+    }
+    
+    fn teleporter_exit(&self, label: char, entrance: Position) -> Option<Position> {
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let position = Position::new(x as i32, y as i32);
+                if *tile == Tile::Teleporter(label) && position != entrance {
+                    return Some(position);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl MapGenerator for MapFile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn max_participants(&self) -> Option<usize> {
+        Some(self.max_players)
+    }
+    
+    fn generate(&self, participants: usize, rng: &mut StdRng) -> (Area, Vec<Position>) {
+        let mut area = Area::new();
+        
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let position = Position::new(x as i32, y as i32);
+                match *tile {
+                    Tile::Block => { make_block(&mut area, position); },
+                    Tile::Abyss => { make_abyss(&mut area, position); },
+                    Tile::Ice => area.place_terrain(position, Terrain::Ice),
+                    Tile::Conveyor(direction) => {
+                        area.place_terrain(position, Terrain::Conveyor(direction));
+                    }
+                    Tile::Crumbling => {
+                        area.place_terrain(position, Terrain::Crumbling(CRUMBLING_CROSSINGS));
+                    }
+                    Tile::Teleporter(label) => {
+                        if let Some(exit) = self.teleporter_exit(label, position) {
+                            area.place_terrain(position, Terrain::Teleporter(exit));
+                        }
+                    }
+                    Tile::Floor | Tile::Spawn(..) => (),
+                }
+            }
+        }
+        
+        surround_with_abyss(&mut area, Rectangle::wh(East * self.width + South * self.height));
+        
+        let mut spawns = self.spawn_slots().into_iter()
+                                           .take(participants)
+                                           .map(|(_, position)| position)
+                                           .collect::<Vec<_>>();
+        rng.shuffle(&mut spawns);
+        
+        (area, spawns)
+    }
+}
+
+
+pub fn parse_map(text: &str) -> Result<MapFile, MapError> {
+    let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+    
+    let mut name = None;
+    let mut size = None;
+    let mut players = None;
+    
+    loop {
+        let (number, line) = match lines.next() {
+            None => return syntax_error(text.lines().count() + 1, 1,
+                                        "missing \"---\" between header and layout".to_string()),
+            Some(line) => line,
+        };
+        let line = line.trim_end();
+        if line == "---" {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        
+        let colon = match line.find(':') {
+            None => return syntax_error(number, 1, "expected \"key: value\"".to_string()),
+            Some(colon) => colon,
+        };
+        let key = line[..colon].trim();
+        let value = line[colon + 1..].trim();
+        let column = colon + 2 + line[colon + 1..].len() - line[colon + 1..].trim_start().len();
+        
+        match key {
+            "name" => name = Some(value.to_string()),
+            "size" => {
+                let mut parts = value.split('x').map(|part| part.trim().parse::<i32>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => {
+                        size = Some((width, height));
+                    }
+                    _ => return syntax_error(number, column,
+                                             format!("invalid size \"{}\", expected WIDTHxHEIGHT",
+                                                     value)),
+                }
+            }
+            "players" => match value.parse::<usize>() {
+                Ok(count) if count >= 2 => players = Some((count, number, column)),
+                _ => return syntax_error(number, column,
+                                         format!("invalid player count \"{}\"", value)),
+            },
+            _ => return syntax_error(number, 1, format!("unknown header \"{}\"", key)),
+        }
+    }
+    
+    let name = match name {
+        None => return syntax_error(1, 1, "missing \"name\" header".to_string()),
+        Some(name) => name,
+    };
+    
+    let mut rows = vec![];
+    let mut first_row = None;
+    let mut slots_used = [false; 10];
+    let mut teleporters: Vec<(char, usize, usize)> = vec![];
+    for (number, line) in lines {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        first_row = first_row.or(Some(number));
+        
+        let mut row = vec![];
+        for (index, character) in line.chars().enumerate() {
+            let tile = match character {
+                '.' => Tile::Floor,
+                '#' => Tile::Block,
+                '~' => Tile::Abyss,
+                '=' => Tile::Ice,
+                '^' => Tile::Conveyor(North),
+                '>' => Tile::Conveyor(East),
+                'v' => Tile::Conveyor(South),
+                '<' => Tile::Conveyor(West),
+                '%' => Tile::Crumbling,
+                '0'...'9' => {
+                    let slot = character as u8 - b'0';
+                    if slots_used[slot as usize] {
+                        return syntax_error(number, index + 1,
+                                            format!("spawn slot {} is used twice", slot));
+                    }
+                    slots_used[slot as usize] = true;
+                    Tile::Spawn(slot)
+                }
+                'a'...'z' => {
+                    match teleporters.iter().filter(|entry| entry.0 == character).count() {
+                        0 | 1 => teleporters.push((character, number, index + 1)),
+                        _ => return syntax_error(number, index + 1,
+                                                 format!("teleporter '{}' has more than two ends",
+                                                         character)),
+                    }
+                    Tile::Teleporter(character)
+                }
+                _ => return syntax_error(number, index + 1,
+                                         format!("unknown tile '{}'", character)),
+            };
+            row.push(tile);
+        }
+        
+        if let Some((width, _)) = size {
+            if row.len() != width as usize {
+                return syntax_error(number, row.len().min(width as usize) + 1,
+                                    format!("row is {} wide but the header says {}",
+                                            row.len(), width));
+            }
+        }
+        else if let Some(first) = rows.first() {
+            let first: &Vec<Tile> = first;
+            if row.len() != first.len() {
+                return syntax_error(number, row.len().min(first.len()) + 1,
+                                    format!("row is {} wide but the first row is {}",
+                                            row.len(), first.len()));
+            }
+        }
+        rows.push(row);
+    }
+    
+    let (width, height) = match size {
+        Some((width, height)) => {
+            if rows.len() != height as usize {
+                return syntax_error(first_row.unwrap_or(1) + rows.len(), 1,
+                                    format!("layout has {} rows but the header says {}",
+                                            rows.len(), height));
+            }
+            (width, height)
+        }
+        None => match rows.first() {
+            None => return syntax_error(text.lines().count() + 1, 1,
+                                        "layout is empty".to_string()),
+            Some(row) => (row.len() as i32, rows.len() as i32),
+        },
+    };
+    
+    for &(label, number, column) in &teleporters {
+        if teleporters.iter().filter(|entry| entry.0 == label).count() == 1 {
+            return syntax_error(number, column,
+                                format!("teleporter '{}' has nowhere to lead", label));
+        }
+    }
+    
+    let spawn_count = slots_used.iter().filter(|used| **used).count();
+    let max_players = match players {
+        None => spawn_count,
+        Some((count, number, column)) => {
+            if count > spawn_count {
+                return syntax_error(number, column,
+                                    format!("{} players but only {} spawn slots",
+                                            count, spawn_count));
+            }
+            count
+        }
+    };
+    if max_players < 2 {
+        return syntax_error(first_row.unwrap_or(1), 1,
+                            "layout needs at least 2 spawn slots".to_string());
+    }
+    
+    Ok(MapFile {
+        name: name,
+        width: width,
+        height: height,
+        max_players: max_players,
+        rows: rows,
+    })
+}
+
+pub fn load_map(path: &Path) -> Result<MapFile, MapError> {
+    let mut text = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+                         .map_err(MapError::Io));
+    parse_map(&text)
+}
+
+// Loads every .map file in the directory, ordered by file name.
+pub fn load_map_directory(directory: &Path) -> Result<Vec<MapFile>, (String, MapError)> {
+    let describe = |path: &Path, error| (path.display().to_string(), error);
+    
+    let mut paths = vec![];
+    let entries = try!(fs::read_dir(directory).map_err(|error| {
+        describe(directory, MapError::Io(error))
+    }));
+    for entry in entries {
+        let path = try!(entry.map_err(|error| describe(directory, MapError::Io(error)))).path();
+        if path.extension().map_or(false, |extension| extension == "map") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    
+    let mut result = vec![];
+    for path in paths {
+        result.push(try!(load_map(&path).map_err(|error| describe(&path, error))));
+    }
+    Ok(result)
+}
+
+
+#[cfg(test)]
+use session::seeded_rng;
+#[cfg(test)]
+use appearance::Appearance;
+
+#[cfg(test)]
+fn error_position(text: &str) -> (usize, usize) {
+    match parse_map(text) {
+        Err(MapError::Syntax { line, column, .. }) => (line, column),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn parsing() {
+    let map = parse_map("name: Crossroads\nsize: 7x3\nplayers: 2\n---\n\
+                         ~~~.~~~\n1..#..2\n~~~.~~~\n").unwrap();
+    assert_eq!(map.name, "Crossroads");
+    assert_eq!((map.width, map.height), (7, 3));
+    assert_eq!(map.max_players, 2);
+    assert_eq!(map.rows[1], vec![Tile::Spawn(1), Tile::Floor, Tile::Floor, Tile::Block,
+                                 Tile::Floor, Tile::Floor, Tile::Spawn(2)]);
+    
+    let map = parse_map("name: Small\n---\n1.2\n.3.\n").unwrap();
+    assert_eq!((map.width, map.height), (3, 2));
+    assert_eq!(map.max_players, 3);
+}
+
+#[test]
+fn error_positions() {
+    assert_eq!(error_position("name: A\n---\n1.2\n.x.\n"), (4, 2));
+    assert_eq!(error_position("name: A\n---\n1.2\n..\n"), (4, 3));
+    assert_eq!(error_position("name: A\nsize: 3x3\n---\n1.2\n...\n"), (6, 1));
+    assert_eq!(error_position("name: A\nsize: 3 by 3\n---\n1.2\n"), (2, 7));
+    assert_eq!(error_position("name: A\nplayers: 3\n---\n1.2\n"), (2, 10));
+    assert_eq!(error_position("name: A\ncolour: red\n---\n1.2\n"), (2, 1));
+    assert_eq!(error_position("name: A\n---\n1.1\n"), (3, 3));
+    assert_eq!(error_position("name: A\n1.2\n"), (2, 1));
+    assert_eq!(error_position("name: A\n"), (2, 1));
+    assert_eq!(error_position("---\n1.2\n"), (1, 1));
+    assert_eq!(error_position("name: A\n---\n1a2\n.b.\n.a.\n"), (4, 2));
+    assert_eq!(error_position("name: A\n---\n1a2\n.a.\n.a.\n"), (5, 2));
+}
+
+#[test]
+fn generating() {
+    let map = parse_map("name: Crossroads\n---\n~~~.~~~\n1..#..2\n~~~.~~~\n3\n").err();
+    assert!(map.is_some());
+    
+    let map = parse_map("name: Crossroads\n---\n~~~.~~~\n1..#..2\n~~~3~~~\n").unwrap();
+    let (area, mut spawns) = map.generate(2, &mut seeded_rng(0));
+    
+    spawns.sort_by_key(|position| position.x);
+    assert_eq!(spawns, vec![Position::new(0, 1), Position::new(6, 1)]);
+    assert_eq!(map.max_participants(), Some(3));
+    
+    assert_eq!(area.appearance_at(Position::new(0, 0)), Appearance::Abyss);
+    assert_eq!(area.appearance_at(Position::new(3, 0)), Appearance::Floor);
+    assert_eq!(area.appearance_at(Position::new(3, 1)), Appearance::Block);
+    assert_eq!(area.appearance_at(Position::new(3, 2)), Appearance::Floor);
+    assert_eq!(area.appearance_at(Position::new(7, 1)), Appearance::Abyss);
+    assert_eq!(area.appearance_at(Position::new(-1, 1)), Appearance::Abyss);
+    
+    let map = parse_map("name: Special\n---\n1=a\n>%2\n.a.\n").unwrap();
+    let (area, _) = map.generate(2, &mut seeded_rng(0));
+    assert_eq!(area.appearance_at(Position::new(1, 0)), Appearance::Ice);
+    assert_eq!(area.appearance_at(Position::new(0, 1)), Appearance::Conveyor(East));
+    assert_eq!(area.appearance_at(Position::new(1, 1)), Appearance::Crumbling);
+    assert_eq!(area.terrain_at(Position::new(2, 0)),
+               Some(Terrain::Teleporter(Position::new(1, 2))));
+    assert_eq!(area.terrain_at(Position::new(1, 2)),
+               Some(Terrain::Teleporter(Position::new(2, 0))));
+}
+
+#[test]
+fn bundled_maps() {
+    let maps = load_map_directory(Path::new("maps")).unwrap();
+    assert!(!maps.is_empty());
+    for map in maps {
+        let (_, spawns) = map.generate(map.max_players, &mut seeded_rng(0));
+        assert_eq!(spawns.len(), map.max_players);
+    }
+}
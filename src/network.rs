@@ -4,12 +4,17 @@ use std::mem;
 use std::net::*;
 use std::time::Duration;
 use std::thread::{self, JoinHandle};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::mpsc::{Sender, Receiver, channel, SendError, RecvError};
 use entity::*;
 use space::*;
 use space::Direction::*;
+use handshake::*;
 use lobby::*;
+use pipe::*;
+use protocol::*;
+use text::*;
+use websocket::*;
 
 
 pub struct Server {
@@ -83,34 +88,202 @@ fn start<A>(tcp_listener: TcpListener, mut callback: A, stopped: Arc<RwLock<bool
     })
 }
 
-pub fn single_lobby<A: ToSocketAddrs>(address: A, timeout: Duration, turn_delay: Duration)
+// Writes to a bot's socket and closes the socket when dropped, which also ends the thread reading
+// from it.
+struct Connection {
+    stream: TcpStream,
+}
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // the bot may have hung up already
+        drop(self.stream.shutdown(Shutdown::Both));
+    }
+}
+
+// Writes each notification to a browser's socket as a WebSocket message, and closes the socket when
+// dropped. The thread reading from the socket shares it to answer pings.
+struct WebSocketConnection {
+    stream: Arc<Mutex<TcpStream>>,
+}
+impl Write for WebSocketConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stream.lock() {
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "Socket lock poisoned.")),
+            Ok(mut stream) => stream.write_all(&text_frame(buf)).map(|()| buf.len()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.lock() {
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "Socket lock poisoned.")),
+            Ok(mut stream) => stream.flush(),
+        }
+    }
+}
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        if let Ok(mut stream) = self.stream.lock() {
+            // the browser may have gone already
+            drop(stream.write_all(&close_frame()));
+            drop(stream.shutdown(Shutdown::Both));
+        }
+    }
+}
+
+// How long a bot has after connecting to start its handshake before it's taken for a bot that
+// doesn't send one.
+const HANDSHAKE_WINDOW: Duration = Duration::from_millis(250);
+
+// Reads the handshake or text greeting if the bot starts with one, or opens the WebSocket a browser
+// asks for. Returns the name to know the bot by, the protocol it speaks, the capabilities it gets
+// and whatever it sent instead of a handshake.
+fn greet(stream: &mut TcpStream, name: String, timeout: Duration)
+        -> io::Result<(String, Protocol, u8, Vec<u8>)> {
+    try!(stream.set_read_timeout(Some(HANDSHAKE_WINDOW)));
+    let mut first = [0];
+    let received = match stream.read(&mut first) {
+        Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Hung up.")),
+        Ok(_) => vec![first[0]],
+        Err(ref error) if error.kind() == io::ErrorKind::WouldBlock
+                          || error.kind() == io::ErrorKind::TimedOut => vec![],
+        Err(error) => return Err(error),
+    };
+    
+    // the rest of the greeting has to follow promptly
+    try!(stream.set_read_timeout(Some(timeout)));
+    // bots that don't shake hands get only the original protocol, since that's all they know
+    let (given, protocol, capabilities, received) = if received == [MAGIC[0]] {
+        let handshake = try!(read_handshake(&mut (&first[..]).chain(&mut *stream)));
+        let (version, capabilities) = try!(accept_handshake(stream, &handshake));
+        (Some(handshake.name), protocol_for_version(version), capabilities, vec![])
+    }
+    else if received == b"G" {
+        // the start of a browser's GET request
+        let request = try!(read_request(&mut (&first[..]).chain(&mut *stream)));
+        let key = match request.key {
+            None => {
+                drop(stream.write_all(BAD_REQUEST.as_bytes()));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a WebSocket request."));
+            }
+            Some(key) => key,
+        };
+        try!(stream.write_all(accept_response(&key).as_bytes()));
+        (Some(name_parameter(&request.path).unwrap_or_default()), Protocol::Json, CAPABILITIES,
+         vec![])
+    }
+    else if received.first().map_or(false, u8::is_ascii_lowercase) {
+        let line = try!(read_line(&mut (&first[..]).chain(&mut *stream)));
+        match greeting_name(&String::from_utf8_lossy(&line)) {
+            // a command rather than a greeting, left for the bot's first turn
+            None => (None, Protocol::Text, CAPABILITIES, line),
+            Some(given) => {
+                try!(stream.write_all(WELCOME.as_bytes()));
+                (Some(given), Protocol::Text, CAPABILITIES, vec![])
+            }
+        }
+    }
+    else {
+        (None, Protocol::Binary, 0, received)
+    };
+    try!(stream.set_read_timeout(None));
+    
+    let name = match given {
+        None => return Ok((name, protocol, capabilities, received)),
+        Some(ref given) if given.is_empty() => name,
+        Some(given) => given,
+    };
+    println!("{} greeted the server with the {} protocol", name, protocol_name(protocol));
+    Ok((name, protocol, capabilities, received))
+}
+
+fn join(lobby: &Mutex<Lobby>, config: &LobbyConfig, participant: Participant) -> io::Result<()> {
+    let mut lobby = match lobby.lock() {
+        Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Lobby lock poisoned.")),
+        Ok(lobby) => lobby,
+    };
+    match lobby.add(participant) {
+        Ok(()) => (),
+        Err(SendError(participant)) => {
+            // Lobby ended because it was empty
+            
+            *lobby = try!(Lobby::new(config.clone()));
+            if lobby.add(participant).is_err() {
+                // Newly created lobby shouldn't end until after first connection
+                
+                return Err(io::Error::new(io::ErrorKind::Other,
+                                          "New lobby did not accept connection."));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Greets the bot, hands it to the lobby and then keeps reading from the socket so that rounds never
+// wait on a bot, only check whether its commands have arrived.
+fn connect_participant(mut stream: TcpStream, name: String, timeout: Duration,
+                       lobby: &Mutex<Lobby>, config: &LobbyConfig)
+        -> io::Result<()> {
+    let (name, protocol, capabilities, received) = try!(greet(&mut stream, name, timeout));
+    
+    let (mut input, mut forward) = pipe();
+    input.set_nonblocking(true);
+    try!(forward.write_all(&received));
+    let mut reading = try!(stream.try_clone());
+    
+    // only browsers speak JSON, and they do it through a WebSocket
+    let (output, replies): (Box<Write>, _) = if protocol == Protocol::Json {
+        let replies = Arc::new(Mutex::new(stream));
+        (Box::new(WebSocketConnection { stream: replies.clone() }), Some(replies))
+    }
+    else {
+        (Box::new(Connection { stream: stream }), None)
+    };
+    
+    let mut participant = Participant::new(Box::new(input), output, name);
+    participant.timeout = Some(timeout);
+    participant.protocol = protocol;
+    participant.capabilities = capabilities;
+    try!(join(lobby, config, participant));
+    
+    // until either end hangs up
+    match replies {
+        None => drop(io::copy(&mut reading, &mut forward)),
+        Some(replies) => drop(forward_messages(&mut reading, &*replies, &mut forward)),
+    }
+    Ok(())
+}
+
+pub fn single_lobby<A: ToSocketAddrs>(address: A, timeout: Duration, config: LobbyConfig)
         -> io::Result<Server> {
-    let mut lobby = try!(Lobby::new(turn_delay));
+    let lobby = Arc::new(Mutex::new(try!(Lobby::new(config.clone()))));
     
     Server::new(address, move|stream: TcpStream, address: SocketAddr| {
-        try!(stream.set_read_timeout(Some(timeout)));
+        let lobby = lobby.clone();
+        let config = config.clone();
+        // anonymous bots are known by their IP address alone, since the port changes every time
+        // they connect; bots sharing a host share a rating
+        let name = address.ip().to_string();
         
-        let stream2 = try!(stream.try_clone());
-        
-        match lobby.add(Participant::new_boxed(stream, stream2)) {
-            Ok(()) => (),
-            Err(SendError(participant)) => {
-                // Lobby ended because it was empty
-                
-                lobby = try!(Lobby::new(turn_delay));
-                if lobby.add(participant).is_err() {
-                    // Newly created lobby shouldn't end until after first connection
-                    
-                    return Err(io::Error::new(io::ErrorKind::Other,
-                                              "New lobby did not accept connection."));
-                }
+        try!(thread::Builder::new().name(format!("Reader for {}", name)).spawn(move|| {
+            if let Err(error) = connect_participant(stream, name, timeout, &lobby, &config) {
+                println!("Could not connect participant - {:?}", error);
             }
-        }
+        }));
         Ok(())
     })
 }
 
 
+#[cfg(test)]
+use std::time::Instant;
+
 #[test]
 fn terminate_explicit() {
     let callback = |_, _| panic!();
@@ -142,7 +315,7 @@ fn terminate_implicit() {
 #[test]
 fn simple_interaction() {
     let server = single_lobby("127.0.0.1:0", Duration::from_millis(99999),
-                              Duration::from_millis(0)).unwrap();
+                              LobbyConfig::new(Duration::from_millis(0))).unwrap();
     {
         let addr = server.addr;
         
@@ -172,8 +345,8 @@ fn simple_interaction() {
         
         let res = client_b.read(&mut buf);
         assert!(res.is_ok(), "{:?}", res);
-        assert_eq!(res.unwrap(), 4);
-        assert_eq!(buf[0..4], [5, 1, 6, 1]);
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(buf[0..5], [5, 1, 6, 1, 1]);
         
         // NewRound, YourTurn, YouSee, Bot, YourTurn = 5 1 6 1 1
     }
@@ -183,34 +356,219 @@ fn simple_interaction() {
 
 #[test]
 fn client_timeout() {
-    let server = single_lobby("127.0.0.1:0", Duration::from_millis(50),
-                              Duration::from_millis(0)).unwrap();
+    let server = single_lobby("127.0.0.1:0", Duration::from_millis(2000),
+                              LobbyConfig::new(Duration::from_millis(0))).unwrap();
     {
         let addr = server.addr;
         
         let mut client_a = TcpStream::connect(addr).unwrap();
         let mut client_b = TcpStream::connect(addr).unwrap();
+        client_b.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+        
+        // client_b carries on while client_a is holding up only itself
+        client_b.write_all(&[1, 0, 0]).unwrap();
+        let mut buf = [0; 5];
+        let res = client_b.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(buf, [5, 1, 6, 1, 1]);
         
-        client_b.write_all(&[1, 0, 0, 1, 0, 0]).unwrap();
+        // a few ticks late, yet long before client_a times out and the round ends
+        thread::sleep(Duration::from_millis(200));
+        client_b.write_all(&[1, 0, 0]).unwrap();
+        let mut buf = [0; 3];
+        let res = client_b.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(buf, [6, 1, 1]);
         
         // LookAt, 0 dx, 0 dy = 1 0 0
+        // NewRound, YourTurn, YouSee, Bot, YourTurn, YouSee, Bot, YourTurn = 5 1 6 1 1 6 1 1
         
-        thread::sleep(Duration::from_millis(100));
+        let mut buf = [0; 4];
+        let res = client_b.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(buf, [7, 9, 1, 2]);
+        
+        // RoundWon, Placement 1 of 2 = 7 9 1 2, client_a having timed out
         
         let mut buf = [0; 10];
         let res = client_a.read(&mut buf);
         assert!(res.is_ok(), "{:?}", res);
         assert_eq!(res.unwrap(), 2);
         assert_eq!(buf[0..2], [5, 1]);
+    }
+    
+    server.stop().join().unwrap().unwrap();
+}
+
+#[test]
+fn handshake() {
+    let mut config = LobbyConfig::new(Duration::from_millis(0));
+    config.rules.tick_limit = Some(3);
+    let leaderboard = config.leaderboard.clone();
+    let server = single_lobby("127.0.0.1:0", Duration::from_millis(99999), config).unwrap();
+    {
+        let addr = server.addr;
+        let looks = [1, 0, 0].iter().cycle().take(30).cloned().collect::<Vec<_>>();
         
-        let mut buf = [200; 10];
-        let res = client_b.read(&mut buf);
+        let mut client_a = TcpStream::connect(addr).unwrap();
+        client_a.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+        let handshake = Handshake::new(9, CAPABILITY_KILLS | 0x80, "alpha".to_string());
+        client_a.write_all(&serialize_handshake(&handshake)).unwrap();
+        assert_eq!(read_reply(&mut client_a).unwrap(), (PROTOCOL_VERSION, CAPABILITY_KILLS));
+        
+        // framed, so a command the server doesn't know, or a Scan it wasn't asked for, is skipped
+        let mut framed = frame(&[20, 1]);
+        framed.extend(frame(&[5, 1]));
+        for look in looks.chunks(3) {
+            framed.extend(frame(look));
+        }
+        client_a.write_all(&framed).unwrap();
+        
+        // client_b skips the handshake
+        let mut client_b = TcpStream::connect(addr).unwrap();
+        let anonymous = client_b.local_addr().unwrap().ip().to_string();
+        client_b.write_all(&looks).unwrap();
+        
+        let mut buf = [0; 18];
+        let res = client_a.read_exact(&mut buf);
         assert!(res.is_ok(), "{:?}", res);
-        let res = res.unwrap();
-        assert!(res == 4, "{:?}", buf);
-        assert_eq!(buf[0..4], [5, 1, 6, 1]);
+        assert_eq!(buf, [0, 1, 5, 0, 1, 1, 0, 1, 12, 0, 1, 1, 0, 1, 12, 0, 1, 1]);
+        
+        // NewRound, YourTurn, CommandError, YourTurn, CommandError, YourTurn, each with its length
+        // in front
+        
+        let started = Instant::now();
+        loop {
+            {
+                let leaderboard = leaderboard.lock().unwrap();
+                if leaderboard.rating("alpha").is_some() {
+                    assert!(leaderboard.rating(&anonymous).is_some());
+                    break;
+                }
+            }
+            assert!(started.elapsed() < Duration::from_millis(2000));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    
+    server.stop().join().unwrap().unwrap();
+}
+
+#[test]
+fn text_protocol() {
+    let mut config = LobbyConfig::new(Duration::from_millis(0));
+    config.rules.tick_limit = Some(3);
+    let server = single_lobby("127.0.0.1:0", Duration::from_millis(99999), config).unwrap();
+    {
+        let addr = server.addr;
+        
+        let mut client_a = TcpStream::connect(addr).unwrap();
+        client_a.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+        client_a.write_all(b"hello Netcat Bot\n").unwrap();
+        let mut buf = [0; 8];
+        let res = client_a.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(&buf, b"welcome\n");
+        client_a.write_all(b"move up\nlook 0 0\nwait 9\n").unwrap();
+        
+        let mut client_b = TcpStream::connect(addr).unwrap();
+        client_b.write_all(&[4, 9]).unwrap();
         
-        // NewRound, YourTurn, YouSee, Bot = 5 1 6 1
+        let expected = "new round\nyour turn\nerror\nyour turn\nsee bot\nyour turn\nsuccess\n";
+        let mut buf = vec![0; expected.len()];
+        let res = client_a.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+    
+    server.stop().join().unwrap().unwrap();
+}
+
+#[test]
+fn websocket() {
+    let mut config = LobbyConfig::new(Duration::from_millis(0));
+    config.rules.tick_limit = Some(3);
+    let leaderboard = config.leaderboard.clone();
+    let server = single_lobby("127.0.0.1:0", Duration::from_millis(99999), config).unwrap();
+    {
+        let addr = server.addr;
+        // browsers mask what they send
+        let message = |text: &str| {
+            let mask = [7, 0, 200, 1];
+            let mut frame = vec![0x81, 0x80 | text.len() as u8];
+            frame.extend_from_slice(&mask);
+            frame.extend(text.bytes().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+            frame
+        };
+        
+        let mut client_a = TcpStream::connect(addr).unwrap();
+        client_a.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+        client_a.write_all(b"GET /?name=Browser%20Bot HTTP/1.1\r\nHost: localhost\r\n\
+                             Upgrade: websocket\r\nConnection: Upgrade\r\n\
+                             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                             Sec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        let expected = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                        Connection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\
+                        \r\n\r\n";
+        let mut buf = vec![0; expected.len()];
+        let res = client_a.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        
+        let mut commands = message("{\"command\": \"jump\"}");
+        commands.extend(message("{\"command\": \"look\", \"dx\": 0, \"dy\": 0}"));
+        commands.extend(message("{\"command\": \"wait\", \"ticks\": 9}"));
+        client_a.write_all(&commands).unwrap();
+        
+        // a TCP bot in the same round
+        let mut client_b = TcpStream::connect(addr).unwrap();
+        let anonymous = client_b.local_addr().unwrap().ip().to_string();
+        client_b.write_all(&[4, 9]).unwrap();
+        
+        let notifications = ["new round", "your turn", "error", "your turn"];
+        for notification in notifications.iter() {
+            let json = format!("{{\"notification\":\"{}\"}}\n", notification);
+            let mut buf = vec![0; json.len() + 2];
+            let res = client_a.read_exact(&mut buf);
+            assert!(res.is_ok(), "{:?}", res);
+            assert_eq!(buf[..2], [0x81, json.len() as u8]);
+            assert_eq!(String::from_utf8(buf[2..].to_vec()).unwrap(), json);
+        }
+        let json = "{\"notification\":\"see\",\"appearance\":\"bot\"}\n";
+        let mut buf = vec![0; json.len() + 2];
+        let res = client_a.read_exact(&mut buf);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(String::from_utf8(buf[2..].to_vec()).unwrap(), json);
+        
+        let started = Instant::now();
+        loop {
+            {
+                let leaderboard = leaderboard.lock().unwrap();
+                if leaderboard.rating("Browser Bot").is_some() {
+                    assert!(leaderboard.rating(&anonymous).is_some());
+                    break;
+                }
+            }
+            assert!(started.elapsed() < Duration::from_millis(2000));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    
+    server.stop().join().unwrap().unwrap();
+}
+
+#[test]
+fn not_a_websocket() {
+    let server = single_lobby("127.0.0.1:0", Duration::from_millis(99999),
+                              LobbyConfig::new(Duration::from_millis(0))).unwrap();
+    {
+        let mut client = TcpStream::connect(server.addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        let res = client.read_to_string(&mut response);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(response, BAD_REQUEST);
     }
     
     server.stop().join().unwrap().unwrap();
@@ -1,5 +1,9 @@
 use std::io::{self, Write, Cursor};
 use appearance::*;
+use protocol::*;
+use text::*;
+use json::*;
+use space::Direction;
 
 
 #[derive(PartialEq, Debug)]
@@ -10,12 +14,37 @@ pub enum Notification {
     Success,
     TooHeavy,
     NewRound,
+    RoundWon,
+    RoundDrawn,
+    Placement {
+        place: u8,
+        participants: u8,
+    },
+    YouScan {
+        radius: u8,
+        appearances: Vec<Appearance>,
+    },
+    // Another participant's bot was destroyed because of something this one did.
+    YouKilled,
+    // The last command was skipped because the server doesn't understand it. Bots using the
+    // unframed binary protocol are disconnected instead, since there's no telling where the
+    // command ended.
+    CommandError,
 }
 
 const CODE_FLOOR: u8 = 0;
 const CODE_BOT: u8 = 1;
 const CODE_BLOCK: u8 = 2;
 const CODE_ABYSS: u8 = 3;
+const CODE_ICE: u8 = 4;
+const CODE_CONVEYOR_NORTH: u8 = 5;
+const CODE_CONVEYOR_EAST: u8 = 6;
+const CODE_CONVEYOR_SOUTH: u8 = 7;
+const CODE_CONVEYOR_WEST: u8 = 8;
+const CODE_TELEPORTER: u8 = 9;
+const CODE_CRUMBLING: u8 = 10;
+const CODE_UNKNOWN: u8 = 11;
+const CODE_ALLY: u8 = 12;
 
 
 fn appearance_to_code(appearance: Appearance) -> u8 {
@@ -24,11 +53,29 @@ fn appearance_to_code(appearance: Appearance) -> u8 {
         Appearance::Bot => CODE_BOT,
         Appearance::Block => CODE_BLOCK,
         Appearance::Abyss => CODE_ABYSS,
+        Appearance::Ice => CODE_ICE,
+        Appearance::Conveyor(Direction::North) => CODE_CONVEYOR_NORTH,
+        Appearance::Conveyor(Direction::East) => CODE_CONVEYOR_EAST,
+        Appearance::Conveyor(Direction::South) => CODE_CONVEYOR_SOUTH,
+        Appearance::Conveyor(Direction::West) => CODE_CONVEYOR_WEST,
+        Appearance::Teleporter => CODE_TELEPORTER,
+        Appearance::Crumbling => CODE_CRUMBLING,
+        Appearance::Unknown => CODE_UNKNOWN,
+        Appearance::Ally => CODE_ALLY,
     }
 }
 
 
-fn serialize_notification(notification: Notification) -> Vec<u8> {
+pub fn serialize_notification(notification: Notification, protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Binary => notification_message(notification),
+        Protocol::Framed => frame(&notification_message(notification)),
+        Protocol::Text => notification_line(notification).into_bytes(),
+        Protocol::Json => notification_json(notification).into_bytes(),
+    }
+}
+
+fn notification_message(notification: Notification) -> Vec<u8> {
     match notification {
         Notification::YourTurn => vec![1],
         Notification::YouDied => vec![2],
@@ -36,27 +83,73 @@ fn serialize_notification(notification: Notification) -> Vec<u8> {
         Notification::TooHeavy => vec![4],
         Notification::NewRound => vec![5],
         Notification::YouSee(appearance) => vec![6, appearance_to_code(appearance)],
+        Notification::RoundWon => vec![7],
+        Notification::RoundDrawn => vec![8],
+        Notification::Placement { place, participants } => vec![9, place, participants],
+        Notification::YouScan { radius, appearances } => {
+            let mut result = vec![10, radius];
+            result.extend(appearances.into_iter().map(appearance_to_code));
+            result
+        }
+        Notification::YouKilled => vec![11],
+        Notification::CommandError => vec![12],
     }
 }
 
 
-pub fn notify(output: &mut Box<Write>, notification: Notification) -> io::Result<()> {
-    output.write_all(serialize_notification(notification).as_ref())
+pub fn notify(output: &mut Box<Write>, notification: Notification, protocol: Protocol)
+        -> io::Result<()> {
+    output.write_all(serialize_notification(notification, protocol).as_ref())
 }
 
 
 #[test]
 fn serializing() {
-    assert_eq!(serialize_notification(Notification::YourTurn), vec![1]);
-    assert_eq!(serialize_notification(Notification::YouDied), vec![2]);
-    assert_eq!(serialize_notification(Notification::Success), vec![3]);
-    assert_eq!(serialize_notification(Notification::TooHeavy), vec![4]);
-    assert_eq!(serialize_notification(Notification::NewRound), vec![5]);
-    assert_eq!(serialize_notification(Notification::YouSee(Appearance::Floor)), vec![6, 0]);
-    assert_eq!(serialize_notification(Notification::YouSee(Appearance::Bot)),
+    let binary = |notification| serialize_notification(notification, Protocol::Binary);
+    
+    assert_eq!(binary(Notification::YourTurn), vec![1]);
+    assert_eq!(binary(Notification::YouDied), vec![2]);
+    assert_eq!(binary(Notification::Success), vec![3]);
+    assert_eq!(binary(Notification::TooHeavy), vec![4]);
+    assert_eq!(binary(Notification::NewRound), vec![5]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Floor)), vec![6, 0]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Bot)),
                vec![6, 1]);
-    assert_eq!(serialize_notification(Notification::YouSee(Appearance::Block)),
+    assert_eq!(binary(Notification::YouSee(Appearance::Block)),
                vec![6, 2]);
-    assert_eq!(serialize_notification(Notification::YouSee(Appearance::Abyss)),
+    assert_eq!(binary(Notification::YouSee(Appearance::Abyss)),
                vec![6, 3]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Ice)), vec![6, 4]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Conveyor(Direction::West))),
+               vec![6, 8]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Crumbling)),
+               vec![6, 10]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Unknown)),
+               vec![6, 11]);
+    assert_eq!(binary(Notification::YouSee(Appearance::Ally)), vec![6, 12]);
+    assert_eq!(binary(Notification::RoundWon), vec![7]);
+    assert_eq!(binary(Notification::RoundDrawn), vec![8]);
+    assert_eq!(binary(Notification::YouKilled), vec![11]);
+    assert_eq!(binary(Notification::CommandError), vec![12]);
+    assert_eq!(binary(Notification::Placement { place: 2, participants: 5 }),
+               vec![9, 2, 5]);
+    assert_eq!(binary(Notification::YouScan {
+                   radius: 0,
+                   appearances: vec![Appearance::Bot],
+               }),
+               vec![10, 0, 1]);
+}
+
+#[test]
+fn framed_serializing() {
+    let framed = |notification| serialize_notification(notification, Protocol::Framed);
+    
+    assert_eq!(framed(Notification::YourTurn), vec![0, 1, 1]);
+    assert_eq!(framed(Notification::YouSee(Appearance::Ally)), vec![0, 2, 6, 12]);
+    assert_eq!(framed(Notification::CommandError), vec![0, 1, 12]);
+    assert_eq!(framed(Notification::YouScan {
+                   radius: 15,
+                   appearances: vec![Appearance::Floor; 31 * 31],
+               })[..4],
+               [3, 195, 10, 15]);
 }
@@ -0,0 +1,114 @@
+// In-memory connections for running bots in the same process as the server, or for handing bytes
+// from a thread reading a socket to the round. Each pipe carries bytes one way; a bot needs two of
+// them.
+
+use std::io::{self, Read, Write, ErrorKind};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+
+
+pub struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    offset: usize,
+    nonblocking: bool,
+}
+impl PipeReader {
+    // Makes reads fail with WouldBlock instead of waiting when nothing has been written yet.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+}
+impl Read for PipeReader {
+    // Blocks until the other end writes something unless set not to. Returns 0 once it has hung
+    // up.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset >= self.pending.len() {
+            let received = if self.nonblocking {
+                self.receiver.try_recv()
+            }
+            else {
+                self.receiver.recv().map_err(|_| TryRecvError::Disconnected)
+            };
+            match received {
+                Err(TryRecvError::Disconnected) => return Ok(0),
+                Err(TryRecvError::Empty) => {
+                    return Err(io::Error::new(ErrorKind::WouldBlock, "Nothing written yet."));
+                }
+                Ok(bytes) => {
+                    self.pending = bytes;
+                    self.offset = 0;
+                }
+            }
+        }
+        let count = buf.len().min(self.pending.len() - self.offset);
+        buf[..count].copy_from_slice(&self.pending[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+
+pub struct PipeWriter {
+    sender: Sender<Vec<u8>>,
+}
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.sender.send(buf.to_vec()) {
+            Err(_) => Err(io::Error::new(ErrorKind::BrokenPipe, "The reader hung up.")),
+            Ok(()) => Ok(buf.len()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let (sender, receiver) = channel();
+    let reader = PipeReader {
+        receiver: receiver,
+        pending: vec![],
+        offset: 0,
+        nonblocking: false,
+    };
+    (reader, PipeWriter { sender: sender })
+}
+
+
+#[cfg(test)]
+use std::thread;
+
+#[test]
+fn piping() {
+    let (mut reader, mut writer) = pipe();
+    writer.write_all(&[1, 2, 3]).unwrap();
+    writer.write_all(&[4]).unwrap();
+    
+    let mut buf = [0; 2];
+    assert_eq!(reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(buf, [1, 2]);
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf[0], 3);
+    
+    let other_thread = thread::spawn(move|| {
+        writer.write_all(&[5]).unwrap();
+    });
+    other_thread.join().unwrap();
+    
+    let mut rest = vec![];
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, vec![4, 5]);
+    
+    let (mut reader, mut writer) = pipe();
+    reader.set_nonblocking(true);
+    assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    writer.write_all(&[6]).unwrap();
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    drop(writer);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    
+    let (reader, mut writer) = pipe();
+    drop(reader);
+    assert!(writer.write_all(&[1]).is_err());
+}
@@ -1,25 +1,194 @@
+use std::collections::HashMap;
+use std::ops::Deref;
 use space::*;
 use entity::*;
 
 
-impl Components<Position> {
-    pub fn at(&self, focus: Position) -> Option<Entity> {
-        for (entity, position) in &self.contents {
-            if *position == focus {
-                return Some(*entity);
+// Positions of entities, indexed by the square they are in so that looking up what occupies a
+// square doesn't have to visit every entity. Only reachable through attach(), set() and detach()
+// so the index can't fall out of step.
+pub struct Positions {
+    components: Components<Position>,
+    grid: HashMap<Position, Vec<Entity>>,
+}
+impl Positions {
+    pub fn new() -> Positions {
+        Positions {
+            components: Components::new(),
+            grid: HashMap::new(),
+        }
+    }
+    
+    pub fn attach(&mut self, entity: Entity, position: Position) {
+        self.detach(entity);
+        self.components.attach(entity, position);
+        self.grid.entry(position).or_insert_with(Vec::new).push(entity);
+    }
+    
+    pub fn detach(&mut self, entity: Entity) -> Option<Position> {
+        let result = self.components.detach(entity);
+        if let Some(position) = result {
+            self.unindex(entity, position);
+        }
+        result
+    }
+    
+    fn unindex(&mut self, entity: Entity, position: Position) {
+        let emptied = match self.grid.get_mut(&position) {
+            None => {
+                debug_unreachable!();
+                return;
             }
+            Some(occupants) => {
+                occupants.retain(|occupant| *occupant != entity);
+                occupants.is_empty()
+            }
+        };
+        if emptied {
+            self.grid.remove(&position);
         }
-        None
+    }
+    
+    pub fn at(&self, focus: Position) -> Option<Entity> {
+        self.grid.get(&focus).and_then(|occupants| occupants.first().map(Clone::clone))
     }
     
     pub fn occupied(&self, focus: Position) -> bool {
-        self.at(focus) != None
+        self.grid.contains_key(&focus)
     }
     
     pub fn set(&mut self, entity: Entity, destination: Position) {
-        match self.of_mut_ref(entity) {
+        match self.components.of(entity) {
             None => (), // Shouldn't happen
-            Some(position) => *position = destination,
+            Some(_) => self.attach(entity, destination),
+        }
+    }
+}
+impl Storage for Positions {
+    fn despawn(&mut self, entity: Entity) {
+        self.detach(entity);
+    }
+    
+    fn has(&self, entity: Entity) -> bool {
+        self.attached(entity)
+    }
+    
+    fn count(&self) -> usize {
+        self.components.count()
+    }
+    
+    fn entities(&self) -> Vec<Entity> {
+        self.components.entities()
+    }
+}
+impl Deref for Positions {
+    type Target = Components<Position>;
+    
+    fn deref(&self) -> &Components<Position> {
+        &self.components
+    }
+}
+
+
+#[cfg(test)]
+use rand::Rng;
+#[cfg(test)]
+use std::time::Instant;
+#[cfg(test)]
+use session::seeded_rng;
+#[cfg(test)]
+use area::*;
+#[cfg(test)]
+use space::Direction::*;
+
+#[cfg(test)]
+fn linear_at(positions: &Positions, focus: Position) -> Vec<Entity> {
+    let mut result = positions.contents.iter()
+                                       .filter(|&(_, position)| *position == focus)
+                                       .map(|(entity, _)| *entity)
+                                       .collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
+#[test]
+fn index_matches_scan() {
+    let mut rng = seeded_rng(0);
+    let mut entities = Entities::new();
+    let mut positions = Positions::new();
+    let bounds = Rectangle::wh(East * 6 + South * 6);
+    let cells = bounds.into_iter().collect::<Vec<_>>();
+    
+    let mut made = vec![];
+    for _ in 0..2000 {
+        match rng.gen_range(0, 4) {
+            0 => {
+                let entity = entities.make();
+                positions.attach(entity, *rng.choose(&cells).unwrap());
+                made.push(entity);
+            }
+            1 => if let Some(entity) = rng.choose(&made) {
+                positions.set(*entity, *rng.choose(&cells).unwrap());
+            },
+            2 => if let Some(entity) = rng.choose(&made) {
+                positions.detach(*entity);
+            },
+            _ => if let Some(entity) = rng.choose(&made) {
+                positions.attach(*entity, *rng.choose(&cells).unwrap());
+            },
+        }
+        
+        for cell in bounds {
+            let scanned = linear_at(&positions, cell);
+            assert_eq!(positions.occupied(cell), !scanned.is_empty());
+            match positions.at(cell) {
+                None => assert!(scanned.is_empty()),
+                Some(entity) => assert!(scanned.contains(&entity)),
+            }
+        }
+    }
+}
+
+#[test]
+fn set_ignores_detached_entities() {
+    let mut entities = Entities::new();
+    let mut positions = Positions::new();
+    let entity = entities.make();
+    
+    positions.set(entity, Position::default());
+    assert_eq!(positions.of(entity), None);
+    assert!(!positions.occupied(Position::default()));
+}
+
+// Hundreds of bots shoving each other around. Run with:
+//     cargo test --release -- --ignored crowded_arena --nocapture
+#[test]
+#[ignore]
+fn crowded_arena() {
+    let mut rng = seeded_rng(0);
+    let mut area = Area::new();
+    let bounds = Rectangle::wh(East * 60 + South * 60);
+    
+    let mut cells = bounds.into_iter().collect::<Vec<_>>();
+    rng.shuffle(&mut cells);
+    let mut bots = vec![];
+    for (index, position) in cells.into_iter().take(1200).enumerate() {
+        if index % 3 == 0 {
+            make_block(&mut area, position);
+        }
+        else {
+            bots.push(make_bot(&mut area, position));
+        }
+    }
+    
+    let start = Instant::now();
+    for _ in 0..50 {
+        for bot in &bots {
+            if area.positions.of(*bot).is_some() {
+                area.go(*bot, *rng.choose(&Direction::all()).unwrap());
+                area.appearance_at(Position::default());
+            }
         }
     }
+    println!("{} bots took {:?} to move 50 times each", bots.len(), start.elapsed());
 }
@@ -0,0 +1,91 @@
+// How commands and notifications are put into bytes for each bot. Every bot starts out with the
+// binary protocol: one byte for each message's code, followed by one byte for each parameter.
+// Bots that shake hands with version 2 or later get the framed protocol, which sends the same bytes
+// with the message's length in front of them, in two bytes with the most significant first.
+// Knowing where each message ends lets the server skip a command it doesn't understand instead of
+// hanging up. Bots that greet the server in text get the text protocol; see text.rs. Bots that
+// connect through a WebSocket get the JSON protocol; see json.rs.
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Protocol {
+    Binary,
+    Framed,
+    Text,
+    Json,
+}
+
+pub fn protocol_for_version(version: u8) -> Protocol {
+    match version {
+        0 | 1 => Protocol::Binary,
+        _ => Protocol::Framed,
+    }
+}
+
+pub fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Binary => "binary",
+        Protocol::Framed => "framed",
+        Protocol::Text => "text",
+        Protocol::Json => "json",
+    }
+}
+
+pub fn protocol(name: &str) -> Option<Protocol> {
+    match name {
+        "binary" => Some(Protocol::Binary),
+        "framed" => Some(Protocol::Framed),
+        "text" => Some(Protocol::Text),
+        "json" => Some(Protocol::Json),
+        _ => None,
+    }
+}
+
+
+pub const MAX_FRAME_LENGTH: usize = 65535;
+
+// Longer messages are cut short; none of the server's come close.
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let length = message.len().min(MAX_FRAME_LENGTH);
+    let mut result = vec![(length >> 8) as u8, length as u8];
+    result.extend_from_slice(&message[..length]);
+    result
+}
+
+// The messages in a stream of frames. A frame that was cut off is left out.
+pub fn unframe(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut result = vec![];
+    let mut index = 0;
+    while index + 2 <= bytes.len() {
+        let length = (bytes[index] as usize) << 8 | bytes[index + 1] as usize;
+        if index + 2 + length > bytes.len() {
+            break;
+        }
+        result.push(&bytes[index + 2..index + 2 + length]);
+        index += 2 + length;
+    }
+    result
+}
+
+
+#[test]
+fn framing() {
+    assert_eq!(frame(&[6, 1]), vec![0, 2, 6, 1]);
+    assert_eq!(frame(&[]), vec![0, 0]);
+    assert_eq!(frame(&vec![0; 300])[..2], [1, 44]);
+    
+    let message = [10, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+    let mut bytes = frame(&[1]);
+    bytes.extend(frame(&message));
+    bytes.extend(frame(&[]));
+    bytes.extend_from_slice(&[0, 3, 9]);
+    assert_eq!(unframe(&bytes), vec![&[1][..], &message[..], &[][..]]);
+}
+
+#[test]
+fn versions() {
+    assert_eq!(protocol_for_version(1), Protocol::Binary);
+    assert_eq!(protocol_for_version(2), Protocol::Framed);
+    assert_eq!(protocol(protocol_name(Protocol::Framed)), Some(Protocol::Framed));
+    assert_eq!(protocol("smoke signals"), None);
+}
@@ -2,6 +2,11 @@ use space::*;
 use area::*;
 use entity::*;
 use cooldown::*;
+use event::*;
+
+
+pub const MOVE_TICKS: u8 = 3;
+pub const DRILL_TICKS: u8 = 5;
 
 
 pub enum DrillResult {
@@ -26,7 +31,9 @@ pub enum Pushable {
 
 
 impl Area {
-    fn push_impl(&mut self, target: Position, direction: Direction, chain: u8) -> PushResult {
+    // The pusher is credited with anything destroyed along the chain.
+    fn push_impl(&mut self, target: Position, direction: Direction, chain: u8, pusher: Entity)
+            -> PushResult {
         match self.positions.at(target) {
             None => return PushResult::Success,
             Some(entity) => {
@@ -39,10 +46,19 @@ impl Area {
                             PushResult::TooHeavy
                         }
                         else {
-                            match self.push_impl(destination, direction, chain + 1) {
-                                PushResult::Success => self.positions.set(entity, destination),
-                                PushResult::TooHeavy | PushResult::DestroysEnterer => {
-                                    self.remove(entity)
+                            match self.push_impl(destination, direction, chain + 1, pusher) {
+                                PushResult::Success => {
+                                    self.shove(entity, destination, direction, pusher);
+                                }
+                                PushResult::TooHeavy => {
+                                    self.emit(Event::Squished {
+                                        entity: entity,
+                                        at: target,
+                                    });
+                                    self.remove(entity, Some(pusher));
+                                }
+                                PushResult::DestroysEnterer => {
+                                    self.fall(entity, destination, Some(pusher));
                                 }
                             }
                             PushResult::Success
@@ -53,14 +69,14 @@ impl Area {
                             PushResult::TooHeavy
                         }
                         else {
-                            match self.push_impl(destination, direction, chain + 1) {
+                            match self.push_impl(destination, direction, chain + 1, pusher) {
                                 PushResult::Success => {
-                                    self.positions.set(entity, destination);
+                                    self.shove(entity, destination, direction, pusher);
                                     PushResult::Success
                                 },
                                 PushResult::TooHeavy => PushResult::TooHeavy,
                                 PushResult::DestroysEnterer => {
-                                    self.remove(entity);
+                                    self.fall(entity, destination, Some(pusher));
                                     PushResult::Success
                                 }
                             }
@@ -73,8 +89,26 @@ impl Area {
         }
     }
     
-    fn push(&mut self, target: Position, direction: Direction) -> PushResult {
-        self.push_impl(target, direction, 0)
+    fn shove(&mut self, entity: Entity, destination: Position, direction: Direction,
+             pusher: Entity) {
+        self.emit(Event::Pushed {
+            entity: entity,
+            direction: direction,
+        });
+        self.step(entity, destination, direction, Some(pusher));
+    }
+    
+    // The killer is whoever sent the entity over the edge, if it didn't go by itself.
+    pub fn fall(&mut self, entity: Entity, abyss: Position, killer: Option<Entity>) {
+        self.emit(Event::Fell {
+            entity: entity,
+            at: abyss,
+        });
+        self.remove(entity, killer);
+    }
+    
+    fn push(&mut self, target: Position, direction: Direction, pusher: Entity) -> PushResult {
+        self.push_impl(target, direction, 0, pusher)
     }
     
     pub fn go(&mut self, entity: Entity, direction: Direction) -> Option<PushResult> {
@@ -84,12 +118,12 @@ impl Area {
                 None
             }
             Some(position) => {
-                self.acted(entity, 3);
+                self.acted(entity, MOVE_TICKS);
                 let destination = position + direction;
-                let push_result = self.push(destination, direction);
+                let push_result = self.push(destination, direction, entity);
                 match push_result {
-                    PushResult::Success => self.positions.set(entity, destination),
-                    PushResult::DestroysEnterer => self.remove(entity),
+                    PushResult::Success => self.step(entity, destination, direction, None),
+                    PushResult::DestroysEnterer => self.fall(entity, destination, None),
                     PushResult::TooHeavy => (),
                 };
                 Some(push_result)
@@ -97,14 +131,19 @@ impl Area {
         }
     }
     
-    fn do_drill(&mut self, target: Position) -> DrillResult {
+    fn do_drill(&mut self, target: Position, driller: Entity) -> DrillResult {
         match self.positions.at(target) {
             None => return DrillResult::Success,
             Some(entity) => {
                 match self.pushables.of(entity) {
                     None => DrillResult::Success,
                     Some(Pushable::Squishable) | Some(Pushable::Heavy) => {
-                        self.remove(entity);
+                        self.emit(Event::Drilled {
+                            entity: entity,
+                            at: target,
+                            driller: driller,
+                        });
+                        self.remove(entity, Some(driller));
                         DrillResult::Success
                     }
                     Some(Pushable::DestroysEnterer) => DrillResult::DestroysEnterer
@@ -120,12 +159,12 @@ impl Area {
                 None
             }
             Some(position) => {
-                self.acted(entity, 5);
+                self.acted(entity, DRILL_TICKS);
                 let destination = position + direction;
-                let push_result = self.do_drill(destination);
+                let push_result = self.do_drill(destination, entity);
                 match push_result {
-                    DrillResult::Success => self.positions.set(entity, destination),
-                    DrillResult::DestroysEnterer => self.remove(entity),
+                    DrillResult::Success => self.step(entity, destination, direction, None),
+                    DrillResult::DestroysEnterer => self.fall(entity, destination, None),
                 };
                 Some(push_result)
             }
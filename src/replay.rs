@@ -0,0 +1,953 @@
+// Recordings of whole rounds. A replay holds the area as it was when the round started and every
+// byte each participant sent and received on each tick, which is enough to play the round again
+// through Area and check that it comes out the same way.
+//
+// Replay files are plain text, one record per line:
+//
+//     squish_the_bots replay 1
+//     map classic
+//     seed 42
+//     origin -1 -1
+//     row ~~~~~
+//     row ~.#.~
+//     row ~~~~~
+//     bot 1 1 Hunter Bot
+//     bot 3 1 Other Bot
+//     tick 0
+//     turn 0 0201 0103
+//     turn 1 0100ff 010601
+//     place 1 Hunter Bot
+//
+// Rows use the map file characters, with '@' for teleporters and '%' for crumbling floor, which
+// get a "teleporter X Y EXIT_X EXIT_Y" or "crumbling X Y CROSSINGS" line each. Turns give the
+// participant's index in the bot list, then the bytes it sent and the bytes it received in
+// hexadecimal, or "-" for none. Bots on a team get a "team BOT TEAM" line and every bot destroyed
+// by another gets a "kill TICK KILLER VICTIM" line, also by index. A bot that was waited on but
+// whose command hadn't arrived by the end of a tick gets a "late TICK BOT" line for that tick.
+// Bots that don't use the binary protocol get a "protocol BOT NAME" line, and bots that can't be
+// sent every optional part of the protocol get a "capabilities BOT FLAGS" line.
+// "view-range N", "line-of-sight", "tick-limit N", "initiative NAME" and "simultaneous" lines
+// record optional rules.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Write, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use area::*;
+use entity::*;
+use space::*;
+use space::Direction::*;
+use session::*;
+use terrain::*;
+use pushable::*;
+use team::*;
+use resolution::*;
+use protocol::*;
+use notification::*;
+use json::*;
+use handshake::*;
+
+
+const HEADER: &'static str = "squish_the_bots replay 1";
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Exchange {
+    pub bot: usize,
+    pub sent: Vec<u8>,
+    pub received: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Frame {
+    pub tick: u32,
+    pub exchanges: Vec<Exchange>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayBot {
+    pub name: String,
+    pub position: Position,
+    pub team: Option<Team>,
+    pub protocol: Protocol,
+    pub capabilities: u8,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replay {
+    pub map_name: String,
+    pub seed: Seed,
+    pub rules: Rules,
+    pub origin: Position,
+    pub rows: Vec<String>,
+    pub teleporters: Vec<(Position, Position)>,
+    pub crumbling: Vec<(Position, u8)>,
+    pub bots: Vec<ReplayBot>,
+    // Only ticks where something was sent or received have a frame.
+    pub frames: Vec<Frame>,
+    pub kills: Vec<Kill>,
+    // The tick and bot of every time a bot's command hadn't arrived by the end of the tick
+    pub late: Vec<(u32, usize)>,
+    pub placements: Vec<(String, usize)>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Kill {
+    pub tick: u32,
+    // Both are indices into the bot list
+    pub killer: usize,
+    pub victim: usize,
+}
+
+
+// A round being recorded, along with which bot in the replay each entity is.
+pub struct Recording {
+    replay: Replay,
+    bots: HashMap<Entity, usize>,
+}
+
+
+impl Area {
+    // Takes a snapshot of the area as it is now and records every exchange from then on.
+    pub fn start_recording(&mut self, map_name: &str) {
+        let mut replay = Replay {
+            map_name: map_name.to_string(),
+            seed: self.seed,
+            rules: self.rules.clone(),
+            origin: Position::default(),
+            rows: vec![],
+            teleporters: vec![],
+            crumbling: vec![],
+            bots: vec![],
+            frames: vec![],
+            kills: vec![],
+            late: vec![],
+            placements: vec![],
+        };
+        let mut bots = HashMap::new();
+        
+        for (index, bot) in self.all_actors().into_iter().enumerate() {
+            bots.insert(bot, index);
+            replay.bots.push(ReplayBot {
+                name: self.names.of(bot).unwrap_or(String::new()),
+                position: self.positions.of(bot).unwrap_or(Position::default()),
+                team: self.teams.of(bot),
+                protocol: self.protocols.of(bot).unwrap_or(Protocol::Binary),
+                capabilities: self.capabilities.of(bot).unwrap_or(CAPABILITIES),
+            });
+        }
+        
+        let mut squares = self.positions.contents.values().map(Clone::clone).collect::<Vec<_>>();
+        squares.extend(self.terrain.keys().map(Clone::clone));
+        if let Some(first) = squares.first().map(Clone::clone) {
+            let (mut low, mut high) = (first, first);
+            for square in &squares {
+                low = Position::new(low.x.min(square.x), low.y.min(square.y));
+                high = Position::new(high.x.max(square.x), high.y.max(square.y));
+            }
+            replay.origin = low;
+            
+            for y in low.y..high.y + 1 {
+                let mut row = String::new();
+                for x in low.x..high.x + 1 {
+                    let position = Position::new(x, y);
+                    row.push(self.square_character(position));
+                    match self.terrain_at(position) {
+                        Some(Terrain::Teleporter(exit)) => {
+                            replay.teleporters.push((position, exit));
+                        }
+                        Some(Terrain::Crumbling(crossings)) => {
+                            replay.crumbling.push((position, crossings));
+                        }
+                        _ => (),
+                    }
+                }
+                replay.rows.push(row);
+            }
+        }
+        
+        self.recording = Some(Recording {
+            replay: replay,
+            bots: bots,
+        });
+    }
+    
+    // Bots are left out; the replay lists them separately.
+    fn square_character(&self, position: Position) -> char {
+        match self.positions.at(position).and_then(|entity| self.pushables.of(entity)) {
+            Some(Pushable::Heavy) => return '#',
+            Some(Pushable::DestroysEnterer) => return '~',
+            _ => (),
+        }
+        match self.terrain_at(position) {
+            None => '.',
+            Some(Terrain::Ice) => '=',
+            Some(Terrain::Conveyor(North)) => '^',
+            Some(Terrain::Conveyor(East)) => '>',
+            Some(Terrain::Conveyor(South)) => 'v',
+            Some(Terrain::Conveyor(West)) => '<',
+            Some(Terrain::Teleporter(..)) => '@',
+            Some(Terrain::Crumbling(..)) => '%',
+        }
+    }
+    
+    pub fn record_sent(&mut self, bot: Entity, bytes: &[u8]) {
+        self.record(bot, bytes, &[]);
+    }
+    
+    pub fn record_received(&mut self, bot: Entity, bytes: &[u8]) {
+        self.record(bot, &[], bytes);
+    }
+    
+    fn record(&mut self, bot: Entity, sent: &[u8], received: &[u8]) {
+        let tick = self.ticks;
+        let recording = match self.recording {
+            None => return,
+            Some(ref mut recording) => recording,
+        };
+        let index = match recording.bots.get(&bot) {
+            None => return,
+            Some(index) => *index,
+        };
+        
+        let frames = &mut recording.replay.frames;
+        if frames.last().map_or(true, |frame| frame.tick != tick) {
+            frames.push(Frame {
+                tick: tick,
+                exchanges: vec![],
+            });
+        }
+        // one exchange per bot per tick
+        let exchanges = &mut frames.last_mut().unwrap().exchanges;
+        let position = match exchanges.iter().position(|exchange| exchange.bot == index) {
+            Some(position) => position,
+            None => {
+                exchanges.push(Exchange {
+                    bot: index,
+                    sent: vec![],
+                    received: vec![],
+                });
+                exchanges.len() - 1
+            }
+        };
+        let exchange = &mut exchanges[position];
+        exchange.sent.extend_from_slice(sent);
+        exchange.received.extend_from_slice(received);
+    }
+    
+    pub fn record_kill(&mut self, killer: Entity, victim: Entity) {
+        let tick = self.ticks;
+        if let Some(ref mut recording) = self.recording {
+            if let (Some(killer), Some(victim)) = (recording.bots.get(&killer),
+                                                   recording.bots.get(&victim)) {
+                recording.replay.kills.push(Kill {
+                    tick: tick,
+                    killer: *killer,
+                    victim: *victim,
+                });
+            }
+        }
+    }
+    
+    // The bot was waited on this tick but its command hadn't arrived yet.
+    pub fn record_late(&mut self, bot: Entity) {
+        let tick = self.ticks;
+        if let Some(ref mut recording) = self.recording {
+            if let Some(index) = recording.bots.get(&bot) {
+                recording.replay.late.push((tick, *index));
+            }
+        }
+    }
+    
+    pub fn stop_recording(&mut self) -> Option<Replay> {
+        self.recording.take().map(|recording| recording.replay)
+    }
+}
+
+
+// Accepts as many bytes as the recorded participant received, so a connection that broke during
+// the recording breaks at the same moment when replayed.
+struct RecordedOutput {
+    remaining: usize,
+}
+impl Write for RecordedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.remaining {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "Connection broke in the recording."));
+        }
+        self.remaining -= buf.len();
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+// Sends what the recorded participant sent, each command on the tick it arrived on. Keeps the area
+// waiting once for every tick the participant was late.
+struct RecordedInput {
+    // Each command's bytes, or nothing for a tick without one
+    chunks: VecDeque<Vec<u8>>,
+}
+impl Read for RecordedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = match self.chunks.front_mut() {
+            None => return Ok(0),
+            Some(chunk) if chunk.is_empty() => 0,
+            Some(chunk) => {
+                let count = buf.len().min(chunk.len());
+                buf[..count].copy_from_slice(&chunk[..count]);
+                chunk.drain(..count);
+                count
+            }
+        };
+        if self.chunks.front().map_or(false, |chunk| chunk.is_empty()) {
+            self.chunks.pop_front();
+        }
+        match count {
+            0 => Err(io::Error::new(ErrorKind::WouldBlock, "Late in the recording.")),
+            count => Ok(count),
+        }
+    }
+}
+
+
+// Rebuilds the area the replay started with. Each bot sends exactly what it sent in the recording.
+// Returns the bots in the order the replay lists them.
+pub fn replay_area(replay: &Replay) -> (Area, Vec<Entity>) {
+    let mut area = Area::new();
+    area.seed = replay.seed;
+    area.rules = replay.rules.clone();
+    
+    let teleporters = replay.teleporters.iter().map(Clone::clone).collect::<HashMap<_, _>>();
+    let crumbling = replay.crumbling.iter().map(Clone::clone).collect::<HashMap<_, _>>();
+    for (y, row) in replay.rows.iter().enumerate() {
+        for (x, character) in row.chars().enumerate() {
+            let position = replay.origin + East * x as i32 + South * y as i32;
+            let terrain = match character {
+                '#' => { make_block(&mut area, position); None },
+                '~' => { make_abyss(&mut area, position); None },
+                '=' => Some(Terrain::Ice),
+                '^' => Some(Terrain::Conveyor(North)),
+                '>' => Some(Terrain::Conveyor(East)),
+                'v' => Some(Terrain::Conveyor(South)),
+                '<' => Some(Terrain::Conveyor(West)),
+                '@' => teleporters.get(&position).map(|exit| Terrain::Teleporter(*exit)),
+                '%' => crumbling.get(&position).map(|crossings| Terrain::Crumbling(*crossings)),
+                _ => None,
+            };
+            if let Some(terrain) = terrain {
+                area.place_terrain(position, terrain);
+            }
+        }
+    }
+    
+    let mut bots = vec![];
+    for (index, bot) in replay.bots.iter().enumerate() {
+        let mut sent = replay.late.iter().filter(|&&(_, late)| late == index)
+                                         .map(|&(tick, _)| (tick, vec![])).collect::<Vec<_>>();
+        let mut received = 0;
+        for frame in &replay.frames {
+            for exchange in frame.exchanges.iter().filter(|exchange| exchange.bot == index) {
+                if !exchange.sent.is_empty() {
+                    sent.push((frame.tick, exchange.sent.clone()));
+                }
+                received += exchange.received.len();
+            }
+        }
+        // a bot is either late on a tick or sends its command on it, never both
+        sent.sort_by_key(|&(tick, _)| tick);
+        
+        let entity = make_bot(&mut area, bot.position);
+        let chunks = sent.into_iter().map(|(_, bytes)| bytes).collect();
+        area.inputs.attach(entity, Box::new(RecordedInput { chunks: chunks }));
+        area.outputs.attach(entity, Box::new(RecordedOutput { remaining: received }));
+        area.names.attach(entity, bot.name.clone());
+        if let Some(team) = bot.team {
+            area.teams.attach(entity, team);
+        }
+        area.protocols.attach(entity, bot.protocol);
+        area.capabilities.attach(entity, bot.capabilities);
+        bots.push(entity);
+    }
+    
+    (area, bots)
+}
+
+// Plays the round again and describes the first difference from the recording, if any.
+pub fn verify_replay(replay: &Replay) -> Result<(), String> {
+    let (mut area, _) = replay_area(replay);
+    area.start_recording(&replay.map_name);
+    let winners = area.act_all(Duration::from_millis(0));
+    let mut result = area.stop_recording().unwrap();
+    result.placements = area.announce_results(&winners);
+    
+    if result.rows != replay.rows || result.bots != replay.bots {
+        return Err("the area could not be rebuilt".to_string());
+    }
+    for (index, expected) in replay.frames.iter().enumerate() {
+        match result.frames.get(index) {
+            None => return Err(format!("the round ended before tick {}", expected.tick)),
+            Some(actual) if actual != expected => {
+                return Err(format!("tick {} went differently", expected.tick.min(actual.tick)));
+            }
+            Some(_) => (),
+        }
+    }
+    if result.frames.len() > replay.frames.len() {
+        return Err(format!("the round went on past tick {}",
+                           replay.frames.last().map_or(0, |frame| frame.tick)));
+    }
+    if result.late != replay.late {
+        return Err("the bots were late at different times".to_string());
+    }
+    if result.kills != replay.kills {
+        return Err("the kills are different".to_string());
+    }
+    if result.placements != replay.placements {
+        return Err("the placements are different".to_string());
+    }
+    Ok(())
+}
+
+
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text == "-" {
+        return Some(vec![]);
+    }
+    if text.len() % 2 != 0 || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len() / 2).map(|index| u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok())
+                       .collect()
+}
+
+impl Replay {
+    // The name of the killer of every kill, so a bot with several kills is listed several times.
+    // Kills of teammates aren't counted.
+    pub fn killers(&self) -> Vec<String> {
+        self.kills.iter().filter_map(|kill| {
+            match (self.bots.get(kill.killer), self.bots.get(kill.victim)) {
+                (Some(killer), Some(victim)) if !same_team(killer.team, victim.team) => {
+                    Some(killer.name.clone())
+                }
+                _ => None,
+            }
+        }).collect()
+    }
+    
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\nmap {}\nseed {}\n", HEADER, self.map_name.replace('\n', " "),
+                               self.seed);
+        if let Some(range) = self.rules.view_range {
+            text.push_str(&format!("view-range {}\n", range));
+        }
+        if self.rules.line_of_sight {
+            text.push_str("line-of-sight\n");
+        }
+        if let Some(limit) = self.rules.tick_limit {
+            text.push_str(&format!("tick-limit {}\n", limit));
+        }
+        if self.rules.initiative != Initiative::Fixed {
+            text.push_str(&format!("initiative {}\n", initiative_name(self.rules.initiative)));
+        }
+        if self.rules.simultaneous {
+            text.push_str("simultaneous\n");
+        }
+        text.push_str(&format!("origin {} {}\n", self.origin.x, self.origin.y));
+        for row in &self.rows {
+            text.push_str(&format!("row {}\n", row));
+        }
+        for &(position, exit) in &self.teleporters {
+            text.push_str(&format!("teleporter {} {} {} {}\n", position.x, position.y,
+                                   exit.x, exit.y));
+        }
+        for &(position, crossings) in &self.crumbling {
+            text.push_str(&format!("crumbling {} {} {}\n", position.x, position.y, crossings));
+        }
+        for bot in &self.bots {
+            text.push_str(&format!("bot {} {} {}\n", bot.position.x, bot.position.y,
+                                   bot.name.replace('\n', " ")));
+        }
+        for (index, bot) in self.bots.iter().enumerate() {
+            if let Some(team) = bot.team {
+                text.push_str(&format!("team {} {}\n", index, team));
+            }
+            if bot.protocol != Protocol::Binary {
+                text.push_str(&format!("protocol {} {}\n", index, protocol_name(bot.protocol)));
+            }
+            if bot.capabilities != CAPABILITIES {
+                text.push_str(&format!("capabilities {} {}\n", index, bot.capabilities));
+            }
+        }
+        for frame in &self.frames {
+            text.push_str(&format!("tick {}\n", frame.tick));
+            for exchange in &frame.exchanges {
+                text.push_str(&format!("turn {} {} {}\n", exchange.bot, hex(&exchange.sent),
+                                       hex(&exchange.received)));
+            }
+        }
+        for kill in &self.kills {
+            text.push_str(&format!("kill {} {} {}\n", kill.tick, kill.killer, kill.victim));
+        }
+        for &(tick, bot) in &self.late {
+            text.push_str(&format!("late {} {}\n", tick, bot));
+        }
+        for &(ref name, place) in &self.placements {
+            text.push_str(&format!("place {} {}\n", place, name.replace('\n', " ")));
+        }
+        text
+    }
+    
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        try!(File::create(path)).write_all(self.to_text().as_bytes())
+    }
+}
+
+
+pub fn parse_replay(text: &str) -> Result<Replay, String> {
+    let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+    match lines.next() {
+        Some((_, line)) if line == HEADER => (),
+        _ => return Err("not a replay file".to_string()),
+    }
+    
+    let mut replay = Replay {
+        map_name: String::new(),
+        seed: 0,
+        rules: Rules::new(),
+        origin: Position::default(),
+        rows: vec![],
+        teleporters: vec![],
+        crumbling: vec![],
+        bots: vec![],
+        frames: vec![],
+        kills: vec![],
+        late: vec![],
+        placements: vec![],
+    };
+    
+    for (number, line) in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let (key, rest) = match line.find(' ') {
+            None => (line, ""),
+            Some(space) => (&line[..space], &line[space + 1..]),
+        };
+        let numbers = rest.split(' ').map(|part| part.parse::<i32>().ok()).collect::<Vec<_>>();
+        let malformed = || format!("line {}: malformed \"{}\" line", number, key);
+        let position = |first: usize| match (numbers.get(first), numbers.get(first + 1)) {
+            (Some(&Some(x)), Some(&Some(y))) => Ok(Position::new(x, y)),
+            _ => Err(malformed()),
+        };
+        let split_name = |rest: &str| {
+            let mut parts = rest.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(first), Some(second), name) => {
+                    Some((first.to_string(), second.to_string(), name.unwrap_or("").to_string()))
+                }
+                _ => None,
+            }
+        };
+        
+        match key {
+            "map" => replay.map_name = rest.to_string(),
+            "seed" => replay.seed = try!(rest.parse().map_err(|_| malformed())),
+            "view-range" => replay.rules.view_range = Some(try!(rest.parse()
+                                                                   .map_err(|_| malformed()))),
+            "line-of-sight" => replay.rules.line_of_sight = true,
+            "tick-limit" => replay.rules.tick_limit = Some(try!(rest.parse()
+                                                                   .map_err(|_| malformed()))),
+            "initiative" => replay.rules.initiative = try!(initiative(rest).ok_or_else(&malformed)),
+            "simultaneous" => replay.rules.simultaneous = true,
+            "origin" => replay.origin = try!(position(0)),
+            "row" => replay.rows.push(rest.to_string()),
+            "teleporter" => {
+                let entrance = try!(position(0));
+                let exit = try!(position(2));
+                replay.teleporters.push((entrance, exit));
+            }
+            "crumbling" => {
+                let square = try!(position(0));
+                let crossings = match numbers.get(2) {
+                    Some(&Some(crossings)) if crossings >= 0 && crossings < 256 => crossings as u8,
+                    _ => return Err(malformed()),
+                };
+                replay.crumbling.push((square, crossings));
+            }
+            "bot" => {
+                let (x, y, name) = try!(split_name(rest).ok_or_else(&malformed));
+                match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => replay.bots.push(ReplayBot {
+                        name: name,
+                        position: Position::new(x, y),
+                        team: None,
+                        protocol: Protocol::Binary,
+                        capabilities: CAPABILITIES,
+                    }),
+                    _ => return Err(malformed()),
+                }
+            }
+            "team" => {
+                let team = match numbers.get(1) {
+                    Some(&Some(team)) if team >= 0 && team < 256 => team as Team,
+                    _ => return Err(malformed()),
+                };
+                let bot = match numbers.get(0) {
+                    Some(&Some(index)) if index >= 0 => replay.bots.get_mut(index as usize),
+                    _ => None,
+                };
+                match bot {
+                    None => return Err(malformed()),
+                    Some(bot) => bot.team = Some(team),
+                }
+            }
+            "protocol" => {
+                let mut parts = rest.split(' ');
+                let index = parts.next().and_then(|index| index.parse::<usize>().ok());
+                match (index.and_then(|index| replay.bots.get_mut(index)),
+                       parts.next().and_then(protocol)) {
+                    (Some(bot), Some(protocol)) => bot.protocol = protocol,
+                    _ => return Err(malformed()),
+                }
+            }
+            "capabilities" => {
+                let capabilities = match numbers.get(1) {
+                    Some(&Some(flags)) if flags >= 0 && flags < 256 => flags as u8,
+                    _ => return Err(malformed()),
+                };
+                let bot = match numbers.get(0) {
+                    Some(&Some(index)) if index >= 0 => replay.bots.get_mut(index as usize),
+                    _ => None,
+                };
+                match bot {
+                    None => return Err(malformed()),
+                    Some(bot) => bot.capabilities = capabilities,
+                }
+            }
+            "tick" => replay.frames.push(Frame {
+                tick: try!(rest.parse().map_err(|_| malformed())),
+                exchanges: vec![],
+            }),
+            "turn" => {
+                let mut parts = rest.split(' ');
+                let exchange = match (parts.next().and_then(|bot| bot.parse().ok()),
+                                      parts.next().and_then(unhex),
+                                      parts.next().and_then(unhex)) {
+                    (Some(bot), Some(sent), Some(received)) if bot < replay.bots.len() => {
+                        Exchange {
+                            bot: bot,
+                            sent: sent,
+                            received: received,
+                        }
+                    }
+                    _ => return Err(malformed()),
+                };
+                match replay.frames.last_mut() {
+                    None => return Err(format!("line {}: turn before the first tick", number)),
+                    Some(frame) => frame.exchanges.push(exchange),
+                }
+            }
+            "kill" => {
+                let index = |offset: usize| match numbers.get(offset) {
+                    Some(&Some(index)) if index >= 0 && (index as usize) < replay.bots.len() => {
+                        Ok(index as usize)
+                    }
+                    _ => Err(malformed()),
+                };
+                let tick = match numbers.get(0) {
+                    Some(&Some(tick)) if tick >= 0 => tick as u32,
+                    _ => return Err(malformed()),
+                };
+                let killer = try!(index(1));
+                let victim = try!(index(2));
+                replay.kills.push(Kill {
+                    tick: tick,
+                    killer: killer,
+                    victim: victim,
+                });
+            }
+            "late" => match (numbers.get(0), numbers.get(1)) {
+                (Some(&Some(tick)), Some(&Some(bot)))
+                        if tick >= 0 && bot >= 0 && (bot as usize) < replay.bots.len() => {
+                    replay.late.push((tick as u32, bot as usize));
+                }
+                _ => return Err(malformed()),
+            },
+            "place" => {
+                let mut parts = rest.splitn(2, ' ');
+                match (parts.next().and_then(|place| place.parse().ok()), parts.next()) {
+                    (Some(place), name) => {
+                        replay.placements.push((name.unwrap_or("").to_string(), place));
+                    }
+                    _ => return Err(malformed()),
+                }
+            }
+            _ => return Err(format!("line {}: unknown record \"{}\"", number, key)),
+        }
+    }
+    
+    Ok(replay)
+}
+
+pub fn load_replay(path: &Path) -> io::Result<Replay> {
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    parse_replay(&text).map_err(|message| {
+        io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+    })
+}
+
+
+// Names the commands in the bytes a participant sent, e.g. "move east".
+pub fn describe_sent(bytes: &[u8], protocol: Protocol) -> String {
+    match protocol {
+        Protocol::Binary => describe_commands(bytes),
+        Protocol::Framed => {
+            unframe(bytes).into_iter().map(describe_commands).collect::<Vec<_>>().join(", ")
+        }
+        Protocol::Text | Protocol::Json => describe_lines(bytes, None),
+    }
+}
+
+fn describe_commands(bytes: &[u8]) -> String {
+    let direction = |code: u8| match code {
+        0 => "north".to_string(),
+        1 => "east".to_string(),
+        2 => "south".to_string(),
+        3 => "west".to_string(),
+        _ => format!("direction {}", code),
+    };
+    
+    let mut result = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let argument = |offset: usize| bytes.get(index + offset).map(Clone::clone);
+        let (description, length) = match (bytes[index], argument(1), argument(2)) {
+            (1, Some(dx), Some(dy)) => (format!("look at {} {}", dx as i8, dy as i8), 3),
+            (2, Some(code), _) => (format!("move {}", direction(code)), 2),
+            (3, Some(code), _) => (format!("drill {}", direction(code)), 2),
+            (4, Some(ticks), _) => (format!("wait {}", ticks), 2),
+            (5, Some(radius), _) => (format!("scan {}", radius), 2),
+            (code, _, _) => (format!("garbage ({})", code), bytes.len() - index),
+        };
+        result.push(description);
+        index += length;
+    }
+    result.join(", ")
+}
+
+// Names the notifications in the bytes a participant received, leaving out Your-Turn.
+pub fn describe_received(bytes: &[u8], protocol: Protocol) -> String {
+    match protocol {
+        Protocol::Binary => describe_notifications(bytes),
+        Protocol::Framed => {
+            unframe(bytes).into_iter().map(describe_notifications)
+                          .filter(|description| !description.is_empty())
+                          .collect::<Vec<_>>().join(", ")
+        }
+        Protocol::Text => describe_lines(bytes, Some("your turn")),
+        Protocol::Json => {
+            describe_lines(bytes, Some(notification_json(Notification::YourTurn).trim()))
+        }
+    }
+}
+
+// Text messages already describe themselves.
+fn describe_lines(bytes: &[u8], skipped: Option<&str>) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    text.lines().map(str::trim).filter(|line| Some(*line) != skipped).collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe_notifications(bytes: &[u8]) -> String {
+    let mut result = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let argument = |offset: usize| bytes.get(index + offset).map(Clone::clone).unwrap_or(0);
+        let (description, length) = match bytes[index] {
+            1 => (None, 1),
+            2 => (Some("died".to_string()), 1),
+            3 => (Some("success".to_string()), 1),
+            4 => (Some("too heavy".to_string()), 1),
+            5 => (Some("new round".to_string()), 1),
+            6 => (Some(format!("sees {}", argument(1))), 2),
+            7 => (Some("won".to_string()), 1),
+            8 => (Some("drew".to_string()), 1),
+            9 => (Some(format!("placed {} of {}", argument(1), argument(2))), 3),
+            10 => {
+                let side = argument(1) as usize * 2 + 1;
+                (Some(format!("scanned {}x{}", side, side)), 2 + side * side)
+            }
+            11 => (Some("killed".to_string()), 1),
+            12 => (Some("not understood".to_string()), 1),
+            code => (Some(format!("unknown ({})", code)), bytes.len() - index),
+        };
+        result.extend(description);
+        index += length;
+    }
+    result.join(", ")
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+#[cfg(test)]
+use pipe::*;
+
+#[cfg(test)]
+fn recorded_round() -> Replay {
+    let mut area = Area::new();
+    area.seed = 7;
+    make_block(&mut area, Position::new(2, 0));
+    make_abyss(&mut area, Position::new(0, 1));
+    area.place_terrain(Position::new(3, 1), Terrain::Teleporter(Position::new(1, 2)));
+    area.place_terrain(Position::new(1, 2), Terrain::Teleporter(Position::new(3, 1)));
+    area.place_terrain(Position::new(2, 2), Terrain::Crumbling(1));
+    
+    let a = make_bot(&mut area, Position::new(1, 0));
+    let b = make_bot(&mut area, Position::new(3, 0));
+    area.inputs.attach(a, Box::new(Cursor::new(vec![2, 1, 5, 1])));
+    area.inputs.attach(b, Box::new(Cursor::new(vec![1, 0, 0xff, 2, 2, 3, 3])));
+    area.outputs.attach(a, Box::new(vec![]));
+    area.outputs.attach(b, Box::new(vec![]));
+    area.names.attach(a, "a".to_string());
+    area.names.attach(b, "b b".to_string());
+    
+    area.start_recording("test");
+    let winners = area.act_all(Duration::from_millis(0));
+    let mut replay = area.stop_recording().unwrap();
+    replay.placements = area.announce_results(&winners);
+    replay
+}
+
+#[test]
+fn recording() {
+    let replay = recorded_round();
+    assert_eq!(replay.origin, Position::new(0, 0));
+    assert_eq!(replay.rows, vec!["..#.".to_string(), "~..@".to_string(), ".@%.".to_string()]);
+    assert_eq!(replay.bots[1], ReplayBot {
+        name: "b b".to_string(),
+        position: Position::new(3, 0),
+        team: None,
+        protocol: Protocol::Binary,
+        capabilities: CAPABILITIES,
+    });
+    assert_eq!(replay.frames[0], Frame {
+        tick: 0,
+        exchanges: vec![
+            Exchange { bot: 0, sent: vec![2, 1], received: vec![1, 3] },
+            Exchange { bot: 1, sent: vec![1, 0, 0xff], received: vec![1, 6, 0] },
+        ],
+    });
+    let exchange = &replay.frames[0].exchanges[1];
+    assert_eq!(describe_sent(&exchange.sent, Protocol::Binary), "look at 0 -1");
+    assert_eq!(describe_received(&exchange.received, Protocol::Binary), "sees 0");
+    assert_eq!(describe_sent(b"look 0 -1\r\nmove east\n", Protocol::Text), "look 0 -1, move east");
+    assert_eq!(describe_received(b"your turn\nsee floor\n", Protocol::Text), "see floor");
+    assert_eq!(describe_received(b"{\"notification\":\"your turn\"}\n{\"notification\":\"won\"}\n",
+                                 Protocol::Json),
+               "{\"notification\":\"won\"}");
+}
+
+#[test]
+fn round_trip() {
+    let replay = recorded_round();
+    assert_eq!(parse_replay(&replay.to_text()), Ok(replay.clone()));
+    
+    let mut limited = replay.clone();
+    limited.rules.tick_limit = Some(40);
+    limited.bots[1].team = Some(3);
+    limited.bots[0].capabilities = CAPABILITY_SCAN;
+    limited.rules.initiative = Initiative::Random;
+    limited.rules.simultaneous = true;
+    limited.kills.push(Kill { tick: 2, killer: 1, victim: 0 });
+    assert_eq!(parse_replay(&limited.to_text()), Ok(limited.clone()));
+    assert_eq!(limited.killers(), vec!["b b".to_string()]);
+    assert_eq!(verify_replay(&replay), Ok(()));
+    
+    let mut tampered = replay.clone();
+    tampered.frames[0].exchanges[0].received = vec![1, 4];
+    assert_eq!(verify_replay(&tampered), Err("tick 0 went differently".to_string()));
+    
+    assert!(parse_replay("name: not a replay\n").is_err());
+    assert!(parse_replay(&format!("{}\nturn 0 02 -\n", HEADER)).is_err());
+}
+
+#[test]
+fn framed_bots() {
+    let mut area = Area::new();
+    area.seed = 3;
+    let a = make_bot(&mut area, Position::new(0, 0));
+    let b = make_bot(&mut area, Position::new(2, 0));
+    let mut commands = frame(&[9]);
+    commands.extend(frame(&[2, 1]));
+    area.inputs.attach(a, Box::new(Cursor::new(commands)));
+    area.inputs.attach(b, Box::new(Cursor::new(vec![4, 9])));
+    area.outputs.attach(a, Box::new(vec![]));
+    area.outputs.attach(b, Box::new(vec![]));
+    area.protocols.attach(a, Protocol::Framed);
+    
+    area.start_recording("test");
+    let winners = area.act_all(Duration::from_millis(0));
+    let mut replay = area.stop_recording().unwrap();
+    replay.placements = area.announce_results(&winners);
+    
+    assert_eq!(replay.bots[0].protocol, Protocol::Framed);
+    let exchange = &replay.frames[0].exchanges[0];
+    assert_eq!(describe_sent(&exchange.sent, Protocol::Framed), "garbage (9)");
+    assert_eq!(describe_received(&exchange.received, Protocol::Framed), "not understood");
+    let exchange = &replay.frames[1].exchanges[0];
+    assert_eq!(describe_sent(&exchange.sent, Protocol::Framed), "move east");
+    assert_eq!(describe_received(&exchange.received, Protocol::Framed), "success");
+    
+    assert_eq!(parse_replay(&replay.to_text()), Ok(replay.clone()));
+    assert_eq!(verify_replay(&replay), Ok(()));
+}
+
+#[test]
+fn late_commands() {
+    let mut area = Area::new();
+    area.rules.tick_limit = Some(6);
+    let slow = make_bot(&mut area, Position::new(0, 0));
+    let fast = make_bot(&mut area, Position::new(0, 2));
+    let (mut input, mut sending) = pipe();
+    input.set_nonblocking(true);
+    area.inputs.attach(slow, Box::new(input));
+    area.inputs.attach(fast, Box::new(Cursor::new(vec![2, 1, 2, 1, 4, 9])));
+    area.outputs.attach(slow, Box::new(vec![]));
+    area.outputs.attach(fast, Box::new(vec![]));
+    
+    area.start_recording("test");
+    for tick in 0..6 {
+        if tick == 2 {
+            sending.write_all(&[2, 1]).unwrap();
+        }
+        area.act_tick();
+    }
+    let mut replay = area.stop_recording().unwrap();
+    replay.placements = area.announce_results(&area.all_actors());
+    
+    // told once and waited on until its command arrived
+    assert_eq!(replay.late, vec![(0, 0), (1, 0), (5, 0)]);
+    assert_eq!(replay.frames[1], Frame {
+        tick: 2,
+        exchanges: vec![Exchange { bot: 0, sent: vec![2, 1], received: vec![3] }],
+    });
+    assert_eq!(parse_replay(&replay.to_text()), Ok(replay.clone()));
+    assert_eq!(verify_replay(&replay), Ok(()));
+    
+    let mut tampered = replay.clone();
+    tampered.late.remove(1);
+    assert!(verify_replay(&tampered).is_err());
+}
@@ -0,0 +1,333 @@
+// The order bots act in within a tick. Normally each bot's command is carried out before the next
+// bot is asked for one, so whoever goes first wins any contest. In simultaneous rounds every ready
+// bot is asked first and the moves and drills are carried out together afterwards:
+//
+// - Bots that would enter the same square all stay where they are.
+// - Bots that would enter each other's squares, head-on or around a loop, all stay where they are.
+// - A bot that moves into a square another bot is leaving goes after that bot, so a line of bots
+//   moving the same way moves together instead of pushing each other.
+// - A bot that gets pushed or destroyed by someone else's move before its own move comes up
+//   loses its move.
+//
+// Bots that stay where they are are told their move was too heavy and use up the time anyway.
+
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng, StdRng};
+use area::*;
+use action::*;
+use entity::*;
+use notification::*;
+use pushable::*;
+use space::*;
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Initiative {
+    // The order participants joined in.
+    Fixed,
+    // Whoever was first on the last tick that anyone acted on is last on the next one.
+    Rotating,
+    // Shuffled every tick.
+    Random,
+}
+
+pub fn initiative(name: &str) -> Option<Initiative> {
+    match name {
+        "fixed" => Some(Initiative::Fixed),
+        "rotating" => Some(Initiative::Rotating),
+        "random" => Some(Initiative::Random),
+        _ => None,
+    }
+}
+
+pub fn initiative_name(initiative: Initiative) -> &'static str {
+    match initiative {
+        Initiative::Fixed => "fixed",
+        Initiative::Rotating => "rotating",
+        Initiative::Random => "random",
+    }
+}
+
+
+// A move or drill waiting to be carried out.
+struct Intent {
+    bot: Entity,
+    command: Command,
+    from: Position,
+    to: Position,
+}
+
+
+impl Area {
+    pub fn turn_order(&self) -> Vec<Entity> {
+        let mut actors = self.all_actors();
+        if actors.is_empty() {
+            return actors;
+        }
+        match self.rules.initiative {
+            Initiative::Fixed => (),
+            Initiative::Rotating => {
+                let first = self.acting_ticks as usize % actors.len();
+                actors.rotate_left(first);
+            }
+            Initiative::Random => {
+                // seeded by the round's seed and the tick so that a replay can shuffle the same way
+                // without knowing how the map was generated
+                let mut rng: StdRng = SeedableRng::from_seed(&[self.seed as usize,
+                                                               self.ticks as usize][..]);
+                rng.shuffle(&mut actors);
+            }
+        }
+        actors
+    }
+    
+    pub fn act_simultaneously(&mut self, commands: Vec<(Entity, Command)>) {
+        let mut intents = vec![];
+        for (bot, command) in commands {
+            let direction = match command {
+                Command::Move(direction) | Command::Drill(direction) => direction,
+                _ => {
+                    self.perform(bot, command);
+                    continue;
+                }
+            };
+            if let Some(from) = self.positions.of(bot) {
+                intents.push(Intent {
+                    bot: bot,
+                    command: command,
+                    from: from,
+                    to: from + direction,
+                });
+            }
+        }
+        
+        let (stopped, order) = schedule(&intents);
+        for index in 0..intents.len() {
+            if stopped[index] {
+                self.stay(&intents[index]);
+            }
+        }
+        for index in order {
+            let intent = &intents[index];
+            match self.positions.of(intent.bot) {
+                None => (),
+                Some(here) if here != intent.from => self.stay(intent),
+                Some(_) => self.perform(intent.bot, intent.command),
+            }
+        }
+    }
+    
+    fn stay(&mut self, intent: &Intent) {
+        let ticks = match intent.command {
+            Command::Drill(..) => DRILL_TICKS,
+            _ => MOVE_TICKS,
+        };
+        self.acted(intent.bot, ticks);
+        self.notify(intent.bot, Notification::TooHeavy);
+    }
+}
+
+
+// Returns which intents are stopped by a conflict and the order to carry out the rest in.
+fn schedule(intents: &[Intent]) -> (Vec<bool>, Vec<usize>) {
+    let mut entering = HashMap::new();
+    for intent in intents {
+        *entering.entry(intent.to).or_insert(0) += 1;
+    }
+    let mut stopped = intents.iter().map(|intent| entering[&intent.to] > 1).collect::<Vec<_>>();
+    
+    let leaving = intents.iter().enumerate().map(|(index, intent)| (intent.from, index))
+                         .collect::<HashMap<_, _>>();
+    let ahead = |index: usize, stopped: &[bool]| {
+        leaving.get(&intents[index].to).map(Clone::clone).filter(|ahead| !stopped[*ahead])
+    };
+    
+    for start in 0..intents.len() {
+        if stopped[start] {
+            continue;
+        }
+        let mut path = vec![start];
+        while let Some(next) = ahead(*path.last().unwrap(), &stopped) {
+            if let Some(position) = path.iter().position(|index| *index == next) {
+                for index in &path[position..] {
+                    stopped[*index] = true;
+                }
+                break;
+            }
+            path.push(next);
+        }
+    }
+    
+    // whoever is ahead goes first; there are no loops left to go around
+    let mut order = vec![];
+    let mut scheduled = vec![false; intents.len()];
+    for start in 0..intents.len() {
+        let mut path = vec![];
+        let mut current = Some(start);
+        while let Some(index) = current {
+            if stopped[index] || scheduled[index] {
+                break;
+            }
+            scheduled[index] = true;
+            path.push(index);
+            current = ahead(index, &stopped);
+        }
+        order.extend(path.into_iter().rev());
+    }
+    (stopped, order)
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+#[cfg(test)]
+use space::Direction::*;
+#[cfg(test)]
+use std::time::Duration;
+#[cfg(test)]
+use session::{Rules, execute_round};
+#[cfg(test)]
+use generator::OpenArena;
+#[cfg(test)]
+use lobby::Participant;
+#[cfg(test)]
+use replay::verify_replay;
+
+#[cfg(test)]
+fn simultaneous_area(bots: &[(Position, Vec<u8>)]) -> (Area, Vec<Entity>) {
+    let mut area = Area::new();
+    area.rules = Rules {
+        simultaneous: true,
+        ..Rules::new()
+    };
+    let entities = bots.iter().map(|&(position, ref commands)| {
+        let bot = make_bot(&mut area, position);
+        area.inputs.attach(bot, Box::new(Cursor::new(commands.clone())));
+        area.outputs.attach(bot, Box::new(vec![]));
+        bot
+    }).collect();
+    (area, entities)
+}
+
+#[test]
+fn orders() {
+    let mut area = Area::new();
+    let bots = (0..3).map(|index| {
+        let bot = make_bot(&mut area, Position::default() + East * index);
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![])));
+        bot
+    }).collect::<Vec<_>>();
+    
+    assert_eq!(area.turn_order(), bots);
+    area.ticks = 4;
+    area.acting_ticks = 4;
+    assert_eq!(area.turn_order(), bots);
+    
+    area.rules.initiative = Initiative::Rotating;
+    assert_eq!(area.turn_order(), vec![bots[1], bots[2], bots[0]]);
+    area.acting_ticks = 5;
+    assert_eq!(area.turn_order(), vec![bots[2], bots[0], bots[1]]);
+    
+    area.rules.initiative = Initiative::Random;
+    let mut orders = vec![];
+    for tick in 0..20 {
+        area.ticks = tick;
+        let order = area.turn_order();
+        assert_eq!(order, area.turn_order());
+        
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, bots);
+        orders.push(order);
+    }
+    assert!(orders.iter().any(|order| *order != bots));
+}
+
+#[test]
+fn same_square() {
+    let origin = Position::default();
+    let (mut area, bots) = simultaneous_area(&[(origin, vec![2, 1]),
+                                               (origin + East * 2, vec![2, 3])]);
+    
+    area.act_tick();
+    assert_eq!(area.positions.of(bots[0]), Some(origin));
+    assert_eq!(area.positions.of(bots[1]), Some(origin + East * 2));
+    assert_eq!(area.cooldowns.of(bots[0]), Some(MOVE_TICKS));
+}
+
+#[test]
+fn head_on() {
+    let origin = Position::default();
+    let corner = origin + North * 5;
+    let (mut area, bots) = simultaneous_area(&[(origin, vec![2, 1]),
+                                               (origin + East, vec![2, 3]),
+                                               (corner, vec![2, 1]),
+                                               (corner + East, vec![2, 2]),
+                                               (corner + East + South, vec![2, 3]),
+                                               (corner + South, vec![2, 0])]);
+    
+    area.act_tick();
+    assert_eq!(area.positions.of(bots[0]), Some(origin));
+    assert_eq!(area.positions.of(bots[1]), Some(origin + East));
+    // going around in a loop
+    assert_eq!(area.positions.of(bots[2]), Some(corner));
+    assert_eq!(area.positions.of(bots[5]), Some(corner + South));
+}
+
+#[test]
+fn following() {
+    let origin = Position::default();
+    let (mut area, bots) = simultaneous_area(&[(origin, vec![2, 1]),
+                                               (origin + East, vec![2, 1]),
+                                               (origin + East * 3, vec![4, 9])]);
+    
+    area.act_tick();
+    assert_eq!(area.positions.of(bots[0]), Some(origin + East));
+    assert_eq!(area.positions.of(bots[1]), Some(origin + East * 2));
+    assert_eq!(area.positions.of(bots[2]), Some(origin + East * 3));
+}
+
+#[test]
+fn replaying() {
+    let wandering = |commands: &[u8]| {
+        Cursor::new(commands.iter().cycle().take(60).cloned().collect::<Vec<_>>())
+    };
+    let mut participants = vec![
+        Participant::new_boxed(wandering(&[2, 0, 2, 1]), vec![], "a".to_string()),
+        Participant::new_boxed(wandering(&[2, 2, 2, 3]), vec![], "b".to_string()),
+        Participant::new_boxed(wandering(&[2, 1, 3, 3]), vec![], "c".to_string()),
+    ];
+    let rules = Rules {
+        initiative: Initiative::Random,
+        simultaneous: true,
+        ..Rules::new()
+    };
+    
+    let replay = execute_round(&mut participants, &OpenArena, &rules, Duration::from_millis(0), 3);
+    assert_eq!(verify_replay(&replay), Ok(()));
+}
+
+#[test]
+fn rotating_with_cooldowns() {
+    let mut area = Area::new();
+    area.rules.initiative = Initiative::Rotating;
+    let bots = (0..3).map(|index| {
+        let bot = make_bot(&mut area, Position::default() + East * (index * 2));
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![2, 0, 2, 0, 2, 0, 2, 0])));
+        area.outputs.attach(bot, Box::new(vec![]));
+        bot
+    }).collect::<Vec<_>>();
+    
+    // every bot moves on the same ticks and waits out its cooldown in between
+    let mut firsts = vec![];
+    while area.ticks < MOVE_TICKS as u32 * 4 {
+        let order = area.turn_order();
+        let before = bots.iter().map(|bot| area.positions.of(*bot)).collect::<Vec<_>>();
+        area.act_tick();
+        let after = bots.iter().map(|bot| area.positions.of(*bot)).collect::<Vec<_>>();
+        if before != after {
+            firsts.push(order[0]);
+        }
+    }
+    assert_eq!(firsts, vec![bots[0], bots[1], bots[2], bots[0]]);
+}
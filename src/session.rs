@@ -1,7 +1,7 @@
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
 use std::io::{Read, Write, Cursor};
 use std::iter::Filter;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map::Entry::*;
 use std::time::Duration;
 use area::*;
@@ -11,20 +11,141 @@ use action::*;
 use entity::*;
 use notification::*;
 use appearance::*;
+use generator::*;
+use lobby::*;
+use replay::*;
+use team::*;
+use resolution::*;
+use protocol::*;
+use handshake::*;
 
 
-pub fn execute_round(participants: &mut Vec<(Box<Read>, Box<Write>)>, delay: Duration) {
-    let mut area = generate_area(participants.drain(..).collect::<Vec<_>>());
+pub type Seed = u32;
+
+pub fn random_seed() -> Seed {
+    thread_rng().gen()
+}
+
+pub fn seeded_rng(seed: Seed) -> StdRng {
+    SeedableRng::from_seed(&[seed as usize][..])
+}
+
+
+// Optional rules that change how a round plays out. Everything is off unless set.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rules {
+    // How far away from its bot a participant can see, counting diagonal steps as one.
+    pub view_range: Option<u8>,
+    // Whether bots and blocks hide what is behind them.
+    pub line_of_sight: bool,
+    // Rounds end after this many ticks and everyone still standing shares first place.
+    pub tick_limit: Option<u32>,
+    // Who acts first within a tick.
+    pub initiative: Initiative,
+    // Whether moves and drills are carried out together at the end of each tick rather than as
+    // soon as each bot sends them.
+    pub simultaneous: bool,
+}
+impl Rules {
+    pub fn new() -> Rules {
+        Rules {
+            view_range: None,
+            line_of_sight: false,
+            tick_limit: None,
+            initiative: Initiative::Fixed,
+            simultaneous: false,
+        }
+    }
+}
+
+
+// Returns the round's replay. Its placements are the name and place of each participant, with those
+// that disconnected placed last.
+pub fn execute_round(participants: &mut Vec<Participant>, generator: &MapGenerator, rules: &Rules,
+                     delay: Duration, seed: Seed) -> Replay {
+    let mut area = generate_area(participants.drain(..).collect::<Vec<_>>(), generator, seed);
+    area.rules = rules.clone();
+    area.start_recording(generator.name());
     
     let winners = area.act_all(delay);
-    // TODO: maybe send win/draw condition notifications
+    let mut replay = area.stop_recording().unwrap();
+    replay.placements = area.announce_results(&winners);
     
-    participants.extend(area.extract_io_pairs());
+    participants.extend(area.extract_participants());
+    replay
+}
+
+
+// Each participant's place given how many ticks each one lasted. Ties share the better place.
+fn placements(lasted: &[u32]) -> Vec<usize> {
+    lasted.iter().map(|a| 1 + lasted.iter().filter(|b| *b > a).count()).collect()
 }
 
 
 impl Area {
-    fn extract_io_pairs(&mut self) -> Vec<(Box<Read>, Box<Write>)> {
+    // Survivors and their fallen teammates share first place and everyone else is placed by how
+    // long they lasted, ahead of any participants that disconnected. A lone participant or team in
+    // first place has won the round; several have drawn it.
+    pub fn announce_results(&mut self, survivors: &[Entity]) -> Vec<(String, usize)> {
+        let mut results = vec![];
+        
+        let mut teams = survivors.iter().map(|bot| self.teams.of(*bot)).collect::<Vec<_>>();
+        let surviving_teams = teams.iter().filter_map(|team| *team).collect::<HashSet<_>>();
+        teams.extend(self.participants_in_waiting.iter().map(|fallen| fallen.team));
+        
+        let mut lasted = survivors.iter().map(|_| u32::max_value()).collect::<Vec<_>>();
+        lasted.extend(self.participants_in_waiting.iter().map(|fallen| match fallen.team {
+            Some(team) if surviving_teams.contains(&team) => u32::max_value(),
+            _ => fallen.tick,
+        }));
+        
+        let places = placements(&lasted);
+        let first_places = places.iter().zip(&teams).filter(|&(place, _)| *place == 1)
+                                 .map(|(_, team)| *team).collect::<Vec<_>>();
+        let first_places = sides(&first_places);
+        // a bot that hangs up while hearing the results keeps its place
+        let disconnected = self.disconnected.clone();
+        let connected = places.len();
+        let participants = connected + disconnected.len();
+        
+        for (index, place) in places.into_iter().enumerate() {
+            let mut outcome = vec![];
+            if place == 1 {
+                outcome.push(if first_places == 1 {
+                    Notification::RoundWon
+                }
+                else {
+                    Notification::RoundDrawn
+                });
+            }
+            outcome.push(Notification::Placement {
+                place: place.min(255) as u8,
+                participants: participants.min(255) as u8,
+            });
+            
+            if index < survivors.len() {
+                results.push((self.names.of(survivors[index]).unwrap_or(String::new()), place));
+                for notification in outcome {
+                    self.notify(survivors[index], notification);
+                }
+                continue;
+            }
+            
+            let fallen = &mut self.participants_in_waiting[index - survivors.len()];
+            results.push((fallen.name.clone(), place));
+            for notification in outcome {
+                if let Err(error) = notify(&mut fallen.output, notification, fallen.protocol) {
+                    println!("{} disconnected - {:?}", fallen.name, error);
+                    break;
+                }
+            }
+        }
+        
+        results.extend(disconnected.into_iter().map(|name| (name, connected + 1)));
+        results
+    }
+    
+    fn extract_participants(&mut self) -> Vec<Participant> {
         let mut readers: HashMap<Entity, Box<Read>> = HashMap::new();
         for (entity, reader) in self.inputs.contents.drain() {
             match readers.entry(entity) {
@@ -33,38 +154,49 @@ impl Area {
             };
         }
         
-        let mut pairs: HashMap<Entity, (Box<Read>, Box<Write>)> = HashMap::new();
+        // ordered by entity so that the next round is seeded with the same participant order
+        let mut pairs: BTreeMap<Entity, Participant> = BTreeMap::new();
         for (entity, writer) in self.outputs.contents.drain() {
+            let name = self.names.detach(entity).unwrap_or(String::new());
             match readers.remove(&entity) {
                 None => debug_unreachable!(),
-                Some(reader) => { pairs.insert(entity, (reader, writer)); },
+                Some(reader) => {
+                    let mut participant = Participant::new(reader, writer, name);
+                    participant.team = self.teams.detach(entity);
+                    participant.timeout = self.timeouts.detach(entity);
+                    participant.protocol = self.protocols.detach(entity)
+                                               .unwrap_or(Protocol::Binary);
+                    participant.capabilities = self.capabilities.detach(entity)
+                                                   .unwrap_or(CAPABILITIES);
+                    pairs.insert(entity, participant);
+                }
             };
         }
         
         let mut result = vec![];
-        for (_, pair) in pairs {
-            result.push(pair);
+        for (_, participant) in pairs {
+            result.push(participant);
         }
-        for pair in self.participants_in_waiting.drain(..) {
-            result.push(pair);
+        for fallen in self.participants_in_waiting.drain(..) {
+            let mut participant = Participant::new(fallen.input, fallen.output, fallen.name);
+            participant.team = fallen.team;
+            participant.timeout = fallen.timeout;
+            participant.protocol = fallen.protocol;
+            participant.capabilities = fallen.capabilities;
+            result.push(participant);
         }
         result
     }
 }
 
 
-fn generate_area(participants: Vec<(Box<Read>, Box<Write>)>) -> Area {
-    let length = 10 + participants.len() as i32;
-    let bounds = Rectangle::wh(East * length + South * length);
-    let mut area = Area::new();
+fn generate_area(participants: Vec<Participant>, generator: &MapGenerator, seed: Seed) -> Area {
+    let mut rng = seeded_rng(seed);
+    let (mut area, mut spawns) = generator.generate(participants.len(), &mut rng);
+    area.seed = seed;
     
-    let mut rng = thread_rng();
-    let limit = rng.gen_range(bounds.area() / 4, bounds.area() * 7 / 8) as usize;
-    
-    let mut positions = random_unoccupied_position_list(&area, bounds, limit);
-    
-    for (reader, writer) in participants {
-        match positions.pop() {
+    for participant in participants {
+        match spawns.pop() {
             None => {
                 debug_unreachable!();
                 break;
@@ -72,78 +204,40 @@ fn generate_area(participants: Vec<(Box<Read>, Box<Write>)>) -> Area {
             Some(position) => {
                 let bot = make_bot(&mut area, position);
                 
-                area.inputs.attach(bot, reader);
-                area.outputs.attach(bot, writer);
+                area.inputs.attach(bot, participant.input);
+                area.outputs.attach(bot, participant.output);
+                area.names.attach(bot, participant.name);
+                if let Some(team) = participant.team {
+                    area.teams.attach(bot, team);
+                }
+                if let Some(timeout) = participant.timeout {
+                    area.timeouts.attach(bot, timeout);
+                }
+                area.protocols.attach(bot, participant.protocol);
+                area.capabilities.attach(bot, participant.capabilities);
             }
         }
     }
     
-    for position in positions {
-        match rng.gen_range(0, 1) {
-            0 => { make_block(&mut area, position); },
-            1 => { make_abyss(&mut area, position); },
-            _ => debug_unreachable!(),
-        };
-    }
-    
-    let outer_bounds = Rectangle::corners(
-        Position::default() + West + North,
-        Position::default() + East * (length + 1) + South * (length + 1)
-    );
-    for position in outer_bounds {
-        if bounds.contains(position) {
-            continue;
-        }
-        make_abyss(&mut area, position);
-    }
-    
     area
 }
 
-fn unoccupied_positions(area: &Area, bounds: Rectangle) -> Vec<Position> {
-    bounds.into_iter().filter(|position| !area.positions.occupied(*position)).collect()
-}
-
-fn random_unoccupied_position(area: &Area, bounds: Rectangle) -> Option<Position> {
-    thread_rng().choose(&unoccupied_positions(area, bounds)).map(Clone::clone)
-}
-
-fn random_unoccupied_position_list(area: &Area, bounds: Rectangle, limit: usize) -> Vec<Position> {
-    // TODO: this only works on new, empty areas
-    let mut rng = thread_rng();
-    let mut positions = bounds.into_iter().collect::<Vec<_>>();
-    rng.shuffle(&mut positions);
-    positions.into_iter().take(limit).collect::<Vec<_>>()
-}
-
 
-#[test]
-fn emptiness() {
-    let area = Area::new();
-    let rect = Rectangle::corners(Position::default(), Position::default());
-    assert_eq!(unoccupied_positions(&area, rect), vec![Position::default()]);
-    for _ in 0..1000 {
-        assert_eq!(random_unoccupied_position(&area, rect), Some(Position::default()));
-    }
-    
-    let area = Area::new();
-    let rect = Rectangle::corners(Position::default(), Position::default() + South);
-    assert_eq!(unoccupied_positions(&area, rect),
-               vec![Position::default(), Position::default() + South]);
-    for _ in 0..1000 {
-        let position = random_unoccupied_position(&area, rect);
-        assert!(position == Some(Position::default()) || position == Some(Position::default() + South));
-    }
-}
+#[cfg(test)]
+use tests::{SharedWrite};
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
 
 #[test]
 fn generation() {
     for i in 0..100 {
         let num_part = i % 20 + 1;
         
-        let mut participants = Vec::<(Box<Read>, Box<Write>)>::new();
+        let mut participants = vec![];
         for _ in 0..num_part {
-            participants.push((Box::new(Cursor::new(vec![])), Box::new(vec![])));
+            participants.push(Participant::new_boxed(Cursor::new(vec![]), vec![], String::new()));
         }
         
         let length = 10 + participants.len() as i32;
@@ -151,7 +245,7 @@ fn generation() {
         let lower_limit = bounds.area() / 4;
         let upper_limit = bounds.area() * 7 / 8;
         
-        let area = generate_area(participants);
+        let area = generate_area(participants, &Classic, i as Seed);
         
         assert_eq!(area.inputs.contents.len(), num_part);
         assert_eq!(area.outputs.contents.len(), num_part);
@@ -165,8 +259,8 @@ fn generation() {
 
 #[test]
 fn outer_boundaries() {
-    for _ in 0..100 {
-        let area = generate_area(vec![]);
+    for i in 0..100 {
+        let area = generate_area(vec![], &Classic, i);
         
         let length = 10;
         let bounds = Rectangle::wh(East * length + South * length);
@@ -187,12 +281,148 @@ fn outer_boundaries() {
 
 #[test]
 fn single_round_disconnection() {
-    let mut participants: Vec<(Box<Read>, Box<Write>)> = vec![
-        (Box::new(Cursor::new([2, 1])), Box::new(vec![])),
-        (Box::new(Cursor::new([])), Box::new(vec![])), // EoF causes disconnection
+    let mut participants = vec![
+        Participant::new_boxed(Cursor::new([2, 1]), vec![], "a".to_string()),
+        // EoF causes disconnection
+        Participant::new_boxed(Cursor::new([]), vec![], "b".to_string()),
     ];
     
-    execute_round(&mut participants, Duration::from_millis(0));
+    let replay = execute_round(&mut participants, &Classic, &Rules::new(),
+                               Duration::from_millis(0), 0);
     
     assert_eq!(participants.len(), 1);
+    assert_eq!(participants[0].name, "a");
+    assert_eq!(replay.placements, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    assert_eq!(verify_replay(&replay), Ok(()));
+}
+
+#[test]
+fn tick_limit() {
+    let waiting = || Cursor::new([4, 255].iter().cycle().take(100).cloned().collect::<Vec<_>>());
+    let mut participants = vec![
+        Participant::new_boxed(waiting(), vec![], "a".to_string()),
+        Participant::new_boxed(waiting(), vec![], "b".to_string()),
+    ];
+    let rules = Rules {
+        tick_limit: Some(1000),
+        ..Rules::new()
+    };
+    
+    let replay = execute_round(&mut participants, &OpenArena, &rules, Duration::from_millis(0), 0);
+    
+    assert_eq!(participants.len(), 2);
+    assert_eq!(replay.placements, vec![("a".to_string(), 1), ("b".to_string(), 1)]);
+    assert_eq!(verify_replay(&replay), Ok(()));
+}
+
+#[test]
+fn placing() {
+    assert_eq!(placements(&[]), Vec::<usize>::new());
+    assert_eq!(placements(&[5]), vec![1]);
+    assert_eq!(placements(&[3, 7, 7, 1]), vec![3, 1, 1, 4]);
+}
+
+#[test]
+fn results() {
+    let output_a = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let output_b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    
+    let mut area = Area::new();
+    let bot_a = make_bot(&mut area, Position::default());
+    let bot_b = make_bot(&mut area, Position::default() + East);
+    make_abyss(&mut area, Position::default() + East * 2);
+    
+    area.inputs.attach(bot_a, Box::new(Cursor::new([2, 1])));
+    area.inputs.attach(bot_b, Box::new(Cursor::new([])));
+    area.outputs.attach(bot_a, Box::new(SharedWrite::new(output_a.clone())));
+    area.outputs.attach(bot_b, Box::new(SharedWrite::new(output_b.clone())));
+    
+    area.names.attach(bot_a, "a".to_string());
+    area.names.attach(bot_b, "b".to_string());
+    
+    let winners = area.act_all(Duration::from_millis(0));
+    let results = area.announce_results(&winners);
+    assert_eq!(results, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    
+    // YourTurn, YouKilled, Success, RoundWon, Placement 1 of 2
+    assert_eq!(*output_a.borrow(), vec![1, 11, 3, 7, 9, 1, 2]);
+    // YourTurn, YouDied, Placement 2 of 2
+    assert_eq!(*output_b.borrow(), vec![1, 2, 9, 2, 2]);
+}
+
+#[test]
+fn team_results() {
+    let output_c = Rc::new(RefCell::new(Vec::<u8>::new()));
+    
+    let mut area = Area::new();
+    let bot_a = make_bot(&mut area, Position::default());
+    let bot_b = make_bot(&mut area, Position::default() + South * 2);
+    let bot_c = make_bot(&mut area, Position::default() + East);
+    make_abyss(&mut area, Position::default() + East * 2);
+    
+    // a pushes c off the edge after b has hung up
+    area.inputs.attach(bot_a, Box::new(Cursor::new([4, 1, 2, 1])));
+    area.inputs.attach(bot_b, Box::new(Cursor::new([])));
+    area.inputs.attach(bot_c, Box::new(Cursor::new([4, 9])));
+    area.outputs.attach(bot_a, Box::new(vec![]));
+    area.outputs.attach(bot_b, Box::new(vec![]));
+    area.outputs.attach(bot_c, Box::new(SharedWrite::new(output_c.clone())));
+    for &(bot, name, team) in &[(bot_a, "a", 0), (bot_b, "b", 1), (bot_c, "c", 1)] {
+        area.names.attach(bot, name.to_string());
+        area.teams.attach(bot, team);
+    }
+    
+    let winners = area.act_all(Duration::from_millis(0));
+    assert_eq!(winners, vec![bot_a]);
+    let results = area.announce_results(&winners);
+    assert_eq!(results, vec![("a".to_string(), 1), ("c".to_string(), 2), ("b".to_string(), 3)]);
+    // YourTurn, Success, YouDied, Placement 2 of 3
+    assert_eq!(*output_c.borrow(), vec![1, 3, 2, 9, 2, 3]);
+    
+    // c falls first but shares the win with a, who outlasts b
+    output_c.borrow_mut().clear();
+    let mut area = Area::new();
+    let bot_a = make_bot(&mut area, Position::default());
+    let bot_b = make_bot(&mut area, Position::default() + South * 2);
+    let bot_c = make_bot(&mut area, Position::default() + East);
+    make_abyss(&mut area, Position::default() + East * 2);
+    make_abyss(&mut area, Position::default() + South * 3);
+    area.inputs.attach(bot_a, Box::new(Cursor::new([4, 9])));
+    area.inputs.attach(bot_b, Box::new(Cursor::new([4, 1, 2, 2])));
+    area.inputs.attach(bot_c, Box::new(Cursor::new([2, 1])));
+    area.outputs.attach(bot_a, Box::new(vec![]));
+    area.outputs.attach(bot_b, Box::new(vec![]));
+    area.outputs.attach(bot_c, Box::new(SharedWrite::new(output_c.clone())));
+    for &(bot, name, team) in &[(bot_a, "a", 0), (bot_b, "b", 1), (bot_c, "c", 0)] {
+        area.names.attach(bot, name.to_string());
+        area.teams.attach(bot, team);
+    }
+    
+    let winners = area.act_all(Duration::from_millis(0));
+    assert_eq!(winners, vec![bot_a]);
+    let results = area.announce_results(&winners);
+    assert_eq!(results, vec![("a".to_string(), 1), ("c".to_string(), 1), ("b".to_string(), 3)]);
+    
+    // YourTurn, YouDied, RoundWon, Placement 1 of 3
+    assert_eq!(*output_c.borrow(), vec![1, 2, 7, 9, 1, 3]);
+}
+
+#[test]
+fn reproducible_generation() {
+    fn layout(seed: Seed) -> Vec<Appearance> {
+        let mut participants = vec![];
+        for _ in 0..4 {
+            participants.push(Participant::new_boxed(Cursor::new(vec![]), vec![], String::new()));
+        }
+        let area = generate_area(participants, &Classic, seed);
+        
+        let bounds = Rectangle::corners(Position::default() + North + West,
+                                        Position::default() + South * 15 + East * 15);
+        bounds.into_iter().map(|position| area.appearance_at(position)).collect()
+    }
+    
+    for seed in 0..20 {
+        assert_eq!(layout(seed), layout(seed));
+    }
+    assert!((1..20).any(|seed| layout(seed) != layout(0)));
 }
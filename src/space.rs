@@ -32,7 +32,7 @@ impl Mul<Direction> for i32 {
 }
 
 
-#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
 pub struct Offset(Vector2<i32>);
 impl Offset {
     fn new(x: i32, y: i32) -> Offset {
@@ -82,7 +82,7 @@ impl Mul<i32> for Offset {
 }
 
 
-#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
 pub struct Position(Vector2<i32>);
 impl Position {
     pub fn new(x: i32, y: i32) -> Position {
@@ -181,6 +181,14 @@ impl Rectangle {
             ((p.y >= self.topleft.y) != (p.y >= bottomright.y))
     }
     
+    pub fn topleft(self) -> Position {
+        self.topleft
+    }
+    
+    pub fn size(self) -> Offset {
+        self.size
+    }
+    
     pub fn area(self) -> i32 {
         (self.size.x * self.size.y).abs()
     }
@@ -0,0 +1,162 @@
+// Teams of bots that win or lose together. A bot without a team is on a team of its own, so a
+// round without teams is every bot for itself.
+
+use std::collections::HashSet;
+use area::*;
+use appearance::*;
+use entity::*;
+use space::*;
+use handshake::*;
+
+
+pub type Team = u8;
+
+
+impl Area {
+    // What the bot sees at the focus, with its teammates told apart from other bots if it asked for
+    // them to be.
+    pub fn appearance_for(&self, bot: Entity, viewer: Position, focus: Position) -> Appearance {
+        let appearance = self.appearance_seen_from(viewer, focus);
+        if appearance != Appearance::Bot || !self.capable(bot, CAPABILITY_ALLIES) {
+            return appearance;
+        }
+        
+        // a bot is not its own teammate
+        let other = self.positions.at(focus).filter(|other| *other != bot)
+                                            .and_then(|other| self.teams.of(other));
+        match (self.teams.of(bot), other) {
+            (Some(mine), Some(theirs)) if mine == theirs => Appearance::Ally,
+            _ => appearance,
+        }
+    }
+    
+    // How many teams still have a participant in the round.
+    pub fn teams_remaining(&self) -> usize {
+        let teams = self.inputs.contents.keys().map(|entity| self.teams.of(*entity));
+        sides(&teams.collect::<Vec<_>>())
+    }
+}
+
+
+// How many different teams there are among these participants' teams.
+pub fn sides(teams: &[Option<Team>]) -> usize {
+    let mut distinct = HashSet::new();
+    let mut loners = 0;
+    for team in teams {
+        match *team {
+            None => loners += 1,
+            Some(team) => { distinct.insert(team); },
+        }
+    }
+    distinct.len() + loners
+}
+
+
+// Whether bots on these teams are on the same side. Bots without a team have no teammates.
+pub fn same_team(a: Option<Team>, b: Option<Team>) -> bool {
+    a.is_some() && a == b
+}
+
+
+// Deals participants out to the teams in turn.
+pub fn assign_teams(count: usize, teams: Team) -> Vec<Option<Team>> {
+    (0..count).map(|index| Some((index % teams.max(1) as usize) as Team)).collect()
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+#[cfg(test)]
+use space::Direction::*;
+
+#[test]
+fn seeing_teammates() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    let friend = make_bot(&mut area, origin + East);
+    let enemy = make_bot(&mut area, origin + West);
+    make_bot(&mut area, origin + North);
+    area.teams.attach(bot, 0);
+    area.teams.attach(friend, 0);
+    area.teams.attach(enemy, 1);
+    
+    assert_eq!(area.appearance_for(bot, origin, origin + East), Appearance::Ally);
+    assert_eq!(area.appearance_for(bot, origin, origin + West), Appearance::Bot);
+    assert_eq!(area.appearance_for(bot, origin, origin + North), Appearance::Bot);
+    assert_eq!(area.appearance_for(bot, origin, origin), Appearance::Bot);
+    assert_eq!(area.appearance_for(enemy, origin + West, origin + East), Appearance::Bot);
+    
+    // unless the bot didn't ask for allies
+    area.capabilities.attach(bot, CAPABILITY_SCAN | CAPABILITY_KILLS);
+    assert_eq!(area.appearance_for(bot, origin, origin + East), Appearance::Bot);
+}
+
+#[test]
+fn remaining() {
+    let mut area = Area::new();
+    assert_eq!(area.teams_remaining(), 0);
+    
+    for (index, team) in [Some(0), Some(1), Some(0), None].iter().enumerate() {
+        let bot = make_bot(&mut area, Position::default() + East * index as i32);
+        area.inputs.attach(bot, Box::new(Cursor::new(vec![])));
+        if let Some(team) = *team {
+            area.teams.attach(bot, team);
+        }
+    }
+    assert_eq!(area.teams_remaining(), 3);
+}
+
+#[test]
+fn assignment() {
+    assert_eq!(assign_teams(5, 2), vec![Some(0), Some(1), Some(0), Some(1), Some(0)]);
+    assert_eq!(assign_teams(2, 3), vec![Some(0), Some(1)]);
+    
+    assert_eq!(sides(&[]), 0);
+    assert_eq!(sides(&[Some(1), Some(1), None, Some(0), None]), 4);
+}
+
+#[cfg(test)]
+use std::rc::Rc;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use tests::*;
+#[cfg(test)]
+use protocol::*;
+
+#[test]
+fn friendly_fire() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    let friend = make_bot(&mut area, origin + East);
+    let enemy = make_bot(&mut area, origin + West);
+    let stranger = make_bot(&mut area, origin + North);
+    let output = Rc::new(RefCell::new(vec![]));
+    for other in &[bot, friend, enemy, stranger] {
+        area.inputs.attach(*other, Box::new(Cursor::new(vec![])));
+        area.outputs.attach(*other, Box::new(vec![]));
+        area.protocols.attach(*other, Protocol::Framed);
+    }
+    area.outputs.attach(bot, Box::new(SharedWrite::new(output.clone())));
+    area.teams.attach(bot, 0);
+    area.teams.attach(friend, 0);
+    area.teams.attach(enemy, 1);
+    area.teams.attach(stranger, 1);
+    area.start_recording("test");
+    
+    area.remove(friend, Some(bot));
+    assert!(output.borrow().is_empty());
+    area.remove(enemy, Some(bot));
+    // YouKilled, framed with a two byte length
+    assert_eq!(*output.borrow(), vec![0, 1, 11]);
+    // credited but not told, having not asked for it
+    area.capabilities.attach(bot, CAPABILITY_SCAN);
+    area.remove(stranger, Some(bot));
+    assert_eq!(*output.borrow(), vec![0, 1, 11]);
+    
+    let replay = area.stop_recording().unwrap();
+    assert_eq!(replay.kills.len(), 2);
+    assert_eq!(replay.killers().len(), 2);
+}
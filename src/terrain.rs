@@ -0,0 +1,279 @@
+// The floor layer. Terrain lies underneath whatever occupies a square and changes how things move
+// across it.
+
+use area::*;
+use space::*;
+use entity::*;
+use pushable::*;
+use event::*;
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Terrain {
+    // Whatever enters keeps sliding the same way until something is in the way.
+    Ice,
+    // Shifts its occupant one square every tick.
+    Conveyor(Direction),
+    // Sends whatever enters to the other end of the pair, if that square is free.
+    Teleporter(Position),
+    // Gives way to the abyss once it has been crossed this many more times.
+    Crumbling(u8),
+}
+
+
+// Ice can't be longer than this, which keeps a slide off the edge of a borderless area finite.
+const SLIDE_LIMIT: u32 = 256;
+
+
+impl Area {
+    pub fn place_terrain(&mut self, position: Position, terrain: Terrain) {
+        self.terrain.insert(position, terrain);
+    }
+    
+    pub fn terrain_at(&self, position: Position) -> Option<Terrain> {
+        self.terrain.get(&position).map(Clone::clone)
+    }
+    
+    // Moves the entity and lets the terrain act on it, as if it had entered the square by moving in
+    // the given direction. The cause is credited if the terrain carries the entity to its death.
+    pub fn step(&mut self, entity: Entity, destination: Position, direction: Direction,
+                cause: Option<Entity>) {
+        // the square may have crumbled away when whatever was on it was pushed off
+        if let Some(occupant) = self.positions.at(destination) {
+            if self.pushables.of(occupant) == Some(Pushable::DestroysEnterer) {
+                self.fall(entity, destination, cause);
+            }
+            return;
+        }
+        self.relocate(entity, destination);
+        self.arrive(entity, direction, cause);
+    }
+    
+    fn relocate(&mut self, entity: Entity, destination: Position) {
+        if let Some(origin) = self.positions.of(entity) {
+            self.positions.set(entity, destination);
+            self.emit(Event::Moved {
+                entity: entity,
+                from: origin,
+                to: destination,
+            });
+            self.depart(origin);
+        }
+    }
+    
+    fn depart(&mut self, origin: Position) {
+        match self.terrain_at(origin) {
+            Some(Terrain::Crumbling(crossings)) if crossings <= 1 => {
+                self.terrain.remove(&origin);
+                self.emit(Event::Crumbled {
+                    at: origin,
+                });
+                make_abyss(self, origin);
+            }
+            Some(Terrain::Crumbling(crossings)) => {
+                self.place_terrain(origin, Terrain::Crumbling(crossings - 1));
+            }
+            _ => (),
+        }
+    }
+    
+    fn arrive(&mut self, entity: Entity, direction: Direction, cause: Option<Entity>) {
+        for _ in 0..SLIDE_LIMIT {
+            let here = match self.positions.of(entity) {
+                None => return,
+                Some(here) => here,
+            };
+            
+            match self.terrain_at(here) {
+                Some(Terrain::Ice) => {
+                    if !self.slide(entity, here + direction, cause) {
+                        return;
+                    }
+                }
+                Some(Terrain::Teleporter(exit)) => {
+                    if !self.positions.occupied(exit) {
+                        self.relocate(entity, exit);
+                    }
+                    return;
+                }
+                _ => return,
+            }
+        }
+    }
+    
+    // Moves the entity one square without pushing anything. Returns whether it moved.
+    fn slide(&mut self, entity: Entity, destination: Position, cause: Option<Entity>) -> bool {
+        match self.positions.at(destination) {
+            None => {
+                self.relocate(entity, destination);
+                true
+            }
+            Some(occupant) => {
+                if self.pushables.of(occupant) == Some(Pushable::DestroysEnterer) {
+                    self.fall(entity, destination, cause);
+                }
+                false
+            }
+        }
+    }
+    
+    pub fn run_conveyors(&mut self) {
+        let mut conveyors = self.terrain.iter()
+            .filter_map(|(position, terrain)| match *terrain {
+                Terrain::Conveyor(direction) => Some((*position, direction)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        conveyors.sort_by_key(|&(position, _)| (position.y, position.x));
+        
+        // found up front so that nothing rides more than one conveyor per tick
+        let riders = conveyors.into_iter()
+            .filter_map(|(position, direction)| {
+                self.positions.at(position).map(|entity| (entity, position, direction))
+            })
+            .collect::<Vec<_>>();
+        
+        for (entity, position, direction) in riders {
+            if self.positions.of(entity) != Some(position) {
+                continue;
+            }
+            if self.slide(entity, position + direction, None) {
+                self.arrive(entity, direction, None);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+use appearance::Appearance;
+#[cfg(test)]
+use space::Direction::*;
+
+#[test]
+fn sliding() {
+    for dir in Direction::all().iter().map(Clone::clone) {
+        let mut area = Area::new();
+        let origin = Position::default();
+        let bot = make_bot(&mut area, origin);
+        for distance in 1..4 {
+            area.place_terrain(origin + dir * distance, Terrain::Ice);
+        }
+        make_block(&mut area, origin + dir * 5);
+        
+        area.go(bot, dir);
+        assert_eq!(area.positions.of(bot), Some(origin + dir * 4));
+        
+        let mut area = Area::new();
+        let bot = make_bot(&mut area, origin);
+        area.place_terrain(origin + dir, Terrain::Ice);
+        make_abyss(&mut area, origin + dir * 2);
+        
+        area.go(bot, dir);
+        assert_eq!(area.positions.of(bot), None);
+    }
+}
+
+#[test]
+fn pushed_onto_ice() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    let block = make_block(&mut area, origin + East);
+    area.place_terrain(origin + East * 2, Terrain::Ice);
+    area.place_terrain(origin + East * 3, Terrain::Ice);
+    
+    area.go(bot, East);
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+    assert_eq!(area.positions.of(block), Some(origin + East * 4));
+}
+
+#[test]
+fn conveying() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    area.place_terrain(origin, Terrain::Conveyor(East));
+    area.place_terrain(origin + East, Terrain::Conveyor(South));
+    make_block(&mut area, origin + East + South);
+    
+    area.run_conveyors();
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+    
+    area.run_conveyors();
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+    
+    make_abyss(&mut area, origin + East + North);
+    area.place_terrain(origin + East, Terrain::Conveyor(North));
+    area.run_conveyors();
+    assert_eq!(area.positions.of(bot), None);
+}
+
+#[test]
+fn teleporting() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let exit = origin + South * 6;
+    let bot = make_bot(&mut area, origin);
+    area.place_terrain(origin + East, Terrain::Teleporter(exit));
+    area.place_terrain(exit, Terrain::Teleporter(origin + East));
+    
+    area.go(bot, East);
+    assert_eq!(area.positions.of(bot), Some(exit));
+    
+    // and back again
+    area.go(bot, East);
+    area.go(bot, West);
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+    
+    // unless the other end is taken
+    area.go(bot, West);
+    make_block(&mut area, exit);
+    area.go(bot, East);
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+}
+
+#[test]
+fn crumbling() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    area.place_terrain(origin + East, Terrain::Crumbling(2));
+    
+    area.go(bot, East);
+    area.go(bot, East);
+    assert_eq!(area.appearance_at(origin + East), Appearance::Crumbling);
+    area.go(bot, West);
+    area.go(bot, West);
+    assert_eq!(area.appearance_at(origin + East), Appearance::Abyss);
+    
+    area.go(bot, East);
+    assert_eq!(area.positions.of(bot), None);
+}
+
+#[test]
+fn pushed_off_crumbling() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    let bot = make_bot(&mut area, origin);
+    let block = make_block(&mut area, origin + East);
+    area.place_terrain(origin + East, Terrain::Crumbling(1));
+    
+    area.go(bot, East);
+    assert_eq!(area.positions.of(block), Some(origin + East * 2));
+    assert_eq!(area.positions.of(bot), None);
+    assert_eq!(area.appearance_at(origin + East), Appearance::Abyss);
+    
+    // further along a chain, whatever is shoved in after it falls instead
+    let mut area = Area::new();
+    let bot = make_bot(&mut area, origin);
+    let first = make_bot(&mut area, origin + East);
+    let second = make_bot(&mut area, origin + East * 2);
+    area.place_terrain(origin + East * 2, Terrain::Crumbling(1));
+    
+    area.go(bot, East);
+    assert_eq!(area.positions.of(second), Some(origin + East * 3));
+    assert_eq!(area.positions.of(first), None);
+    assert_eq!(area.positions.of(bot), Some(origin + East));
+    assert_eq!(area.appearance_at(origin + East * 2), Appearance::Abyss);
+}
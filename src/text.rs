@@ -0,0 +1,182 @@
+// The line-oriented protocol, for bots that would rather read and write text, such as scripts
+// talking through netcat. Commands are lines like "look -1 1", "move north", "drill east",
+// "wait 3" and "scan 2", and notifications are lines like "your turn", "see bot", "success" and
+// "died". A bot picks this protocol by sending "hello", optionally followed by its name, as soon as
+// it connects. Since every command ends at a newline, one the server doesn't understand is skipped
+// and answered with "error" instead of hanging up.
+
+use std::io::{self, Read};
+use appearance::*;
+use notification::*;
+use space::Direction;
+
+
+pub const GREETING: &'static str = "hello";
+pub const WELCOME: &'static str = "welcome\n";
+
+// Longer lines are taken for a bot that isn't speaking the protocol at all.
+pub const MAX_LINE_LENGTH: usize = 256;
+
+
+// The name a bot gave in its greeting, which is empty if it left its name out, or None if the line
+// isn't a greeting.
+pub fn greeting_name(line: &str) -> Option<String> {
+    let mut words = line.trim().splitn(2, ' ');
+    match (words.next(), words.next()) {
+        (Some(GREETING), name) => Some(name.unwrap_or("").trim().to_string()),
+        _ => None,
+    }
+}
+
+
+// Reads up to and including the next newline, one byte at a time so as not to read past it.
+pub fn read_line(input: &mut Read) -> io::Result<Vec<u8>> {
+    let mut line = vec![];
+    try!(continue_line(input, &mut line, MAX_LINE_LENGTH));
+    Ok(line)
+}
+
+// Adds to the line until it ends in a newline, one byte at a time so as not to read past it. If the
+// input gives up waiting first, whatever arrived stays in the line to be continued later.
+pub fn continue_line(input: &mut Read, line: &mut Vec<u8>, max_length: usize) -> io::Result<()> {
+    let mut buf = [0];
+    while line.last() != Some(&b'\n') {
+        if line.len() >= max_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Line too long."));
+        }
+        match input.read(&mut buf) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Line cut off.")),
+            Ok(_) => line.push(buf[0]),
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => (),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+
+pub fn direction_code(word: &str) -> Option<u8> {
+    match word {
+        "north" => Some(0),
+        "east" => Some(1),
+        "south" => Some(2),
+        "west" => Some(3),
+        _ => None,
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::North => "north",
+        Direction::East => "east",
+        Direction::South => "south",
+        Direction::West => "west",
+    }
+}
+
+// The binary message for a command line, so that both protocols are checked the same way. None if
+// the line isn't shaped like a command.
+pub fn command_message(line: &[u8]) -> Option<Vec<u8>> {
+    let line = match String::from_utf8(line.to_vec()) {
+        Err(..) => return None,
+        Ok(line) => line,
+    };
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let number = |index: usize| words.get(index).and_then(|word| word.parse::<u8>().ok());
+    let offset = |index: usize| {
+        words.get(index).and_then(|word| word.parse::<i8>().ok()).map(|offset| offset as u8)
+    };
+    let direction = || words.get(1).and_then(|word| direction_code(word));
+    
+    match (words.first().map(|word| *word), words.len()) {
+        (Some("look"), 3) => offset(1).and_then(|dx| offset(2).map(|dy| vec![1, dx, dy])),
+        (Some("move"), 2) => direction().map(|code| vec![2, code]),
+        (Some("drill"), 2) => direction().map(|code| vec![3, code]),
+        (Some("wait"), 2) => number(1).map(|ticks| vec![4, ticks]),
+        (Some("scan"), 2) => number(1).map(|radius| vec![5, radius]),
+        _ => None,
+    }
+}
+
+
+pub fn appearance_name(appearance: Appearance) -> String {
+    match appearance {
+        Appearance::Floor => "floor".to_string(),
+        Appearance::Bot => "bot".to_string(),
+        Appearance::Ally => "ally".to_string(),
+        Appearance::Block => "block".to_string(),
+        Appearance::Abyss => "abyss".to_string(),
+        Appearance::Ice => "ice".to_string(),
+        Appearance::Conveyor(direction) => format!("conveyor-{}", direction_name(direction)),
+        Appearance::Teleporter => "teleporter".to_string(),
+        Appearance::Crumbling => "crumbling".to_string(),
+        Appearance::Unknown => "unknown".to_string(),
+    }
+}
+
+pub fn notification_line(notification: Notification) -> String {
+    let line = match notification {
+        Notification::YourTurn => "your turn".to_string(),
+        Notification::YouDied => "died".to_string(),
+        Notification::Success => "success".to_string(),
+        Notification::TooHeavy => "too heavy".to_string(),
+        Notification::NewRound => "new round".to_string(),
+        Notification::YouSee(appearance) => format!("see {}", appearance_name(appearance)),
+        Notification::RoundWon => "won".to_string(),
+        Notification::RoundDrawn => "drew".to_string(),
+        Notification::Placement { place, participants } => {
+            format!("placed {} of {}", place, participants)
+        }
+        Notification::YouScan { radius, appearances } => {
+            let mut line = format!("scanned {}", radius);
+            for appearance in appearances {
+                line.push(' ');
+                line.push_str(&appearance_name(appearance));
+            }
+            line
+        }
+        Notification::YouKilled => "killed".to_string(),
+        Notification::CommandError => "error".to_string(),
+    };
+    line + "\n"
+}
+
+
+#[test]
+fn greetings() {
+    assert_eq!(greeting_name("hello\r\n"), Some(String::new()));
+    assert_eq!(greeting_name("hello Netcat Bot\n"), Some("Netcat Bot".to_string()));
+    assert_eq!(greeting_name("look 0 0\n"), None);
+}
+
+#[test]
+fn commands() {
+    assert_eq!(command_message(b"look -1 1\n"), Some(vec![1, 255, 1]));
+    assert_eq!(command_message(b"  move north\r\n"), Some(vec![2, 0]));
+    assert_eq!(command_message(b"drill east\n"), Some(vec![3, 1]));
+    assert_eq!(command_message(b"wait 3\n"), Some(vec![4, 3]));
+    assert_eq!(command_message(b"scan 2\n"), Some(vec![5, 2]));
+    
+    assert_eq!(command_message(b"look 1\n"), None);
+    assert_eq!(command_message(b"look 200 0\n"), None);
+    assert_eq!(command_message(b"move up\n"), None);
+    assert_eq!(command_message(b"Move north\n"), None);
+    assert_eq!(command_message(b"wait -1\n"), None);
+    assert_eq!(command_message(b"\n"), None);
+    assert_eq!(command_message(&[b'w', b'a', b'i', b't', b' ', 0xff, b'\n']), None);
+}
+
+#[test]
+fn notifications() {
+    assert_eq!(notification_line(Notification::YourTurn), "your turn\n");
+    assert_eq!(notification_line(Notification::YouSee(Appearance::Bot)), "see bot\n");
+    assert_eq!(notification_line(Notification::YouSee(Appearance::Conveyor(Direction::West))),
+               "see conveyor-west\n");
+    assert_eq!(notification_line(Notification::Placement { place: 2, participants: 5 }),
+               "placed 2 of 5\n");
+    assert_eq!(notification_line(Notification::YouScan {
+                   radius: 1,
+                   appearances: vec![Appearance::Floor; 4],
+               }),
+               "scanned 1 floor floor floor floor\n");
+}
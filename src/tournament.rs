@@ -0,0 +1,364 @@
+// Matches between bots that run in this process, played at full speed without any networking.
+// Every pairing plays a number of rounds, swapping who spawns first, and the results are tallied
+// into a matrix of how often each bot beat each other bot.
+
+use std::io;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use example_bots::{drifter, hunter};
+use generator::*;
+use lobby::*;
+use notification::*;
+use pipe::*;
+use session::*;
+
+
+// Plays until the server hangs up.
+pub type Strategy = fn(PipeReader, PipeWriter, String);
+
+fn hunter(input: PipeReader, output: PipeWriter, name: String) {
+    hunter::play(input, output, name, false);
+}
+
+fn drifter(input: PipeReader, output: PipeWriter, name: String) {
+    drifter::play(input, output, name, false);
+}
+
+pub fn strategy(name: &str) -> Option<Strategy> {
+    match name {
+        "hunter" => Some(hunter),
+        "drifter" => Some(drifter),
+        _ => None,
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Entrant {
+    // Must be unique within a tournament because rounds report placements by name.
+    pub name: String,
+    pub strategy: Strategy,
+}
+impl Entrant {
+    pub fn new(name: String, strategy: Strategy) -> Entrant {
+        Entrant {
+            name: name,
+            strategy: strategy,
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct TournamentConfig {
+    pub rounds_per_match: u32,
+    // Each round's seed counts up from this one.
+    pub seed: Seed,
+    // Rounds rotate through these in order.
+    pub maps: Vec<Arc<MapGenerator>>,
+    pub rules: Rules,
+}
+impl TournamentConfig {
+    pub fn new() -> TournamentConfig {
+        TournamentConfig {
+            rounds_per_match: 10,
+            seed: 0,
+            maps: vec![Arc::new(Classic)],
+            rules: Rules {
+                // bots that never find each other would otherwise hold up the whole tournament
+                tick_limit: Some(2000),
+                ..Rules::new()
+            },
+        }
+    }
+}
+
+
+// One entrant's rounds against one opponent.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Record {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+impl Record {
+    pub fn rounds(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+    
+    // Draws count as half a win.
+    pub fn score(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+    
+    pub fn win_rate(&self) -> Option<f64> {
+        match self.rounds() {
+            0 => None,
+            rounds => Some(self.score() / rounds as f64),
+        }
+    }
+    
+    pub fn confidence_interval(&self) -> Option<(f64, f64)> {
+        self.win_rate().map(|rate| wilson_interval(rate, self.rounds()))
+    }
+    
+    fn reversed(&self) -> Record {
+        Record {
+            wins: self.losses,
+            draws: self.draws,
+            losses: self.wins,
+        }
+    }
+}
+
+
+// The Wilson score interval at 95% confidence for a rate observed over some number of trials.
+pub fn wilson_interval(rate: f64, trials: u32) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let z = 1.96;
+    let n = trials as f64;
+    let denominator = 1.0 + z * z / n;
+    let center = (rate + z * z / (2.0 * n)) / denominator;
+    let spread = z * (rate * (1.0 - rate) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+    ((center - spread).max(0.0), (center + spread).min(1.0))
+}
+
+
+pub struct Standings {
+    pub entrants: Vec<Entrant>,
+    // records[a][b] is how entrant a did against entrant b
+    pub records: Vec<Vec<Record>>,
+}
+impl Standings {
+    fn new(entrants: &[Entrant]) -> Standings {
+        Standings {
+            entrants: entrants.to_vec(),
+            records: entrants.iter().map(|_| vec![Record::default(); entrants.len()]).collect(),
+        }
+    }
+    
+    fn record(&mut self, a: usize, b: usize, record: Record) {
+        let total = &mut self.records[a][b];
+        total.wins += record.wins;
+        total.draws += record.draws;
+        total.losses += record.losses;
+        self.records[b][a] = total.reversed();
+    }
+    
+    pub fn total(&self, entrant: usize) -> Record {
+        let mut total = Record::default();
+        for record in &self.records[entrant] {
+            total.wins += record.wins;
+            total.draws += record.draws;
+            total.losses += record.losses;
+        }
+        total
+    }
+    
+    // Each row is an entrant's win rate against each column's entrant, with the 95% confidence
+    // interval underneath.
+    pub fn matrix(&self) -> String {
+        let width = self.entrants.iter().map(|entrant| entrant.name.len()).max().unwrap_or(0)
+                                        .max(11);
+        let pad = |text: &str| format!("{:>1$}", text, width + 2);
+        
+        let mut result = pad("");
+        for entrant in &self.entrants {
+            result.push_str(&pad(&entrant.name));
+        }
+        result.push_str(&pad("overall"));
+        result.push('\n');
+        
+        for (index, entrant) in self.entrants.iter().enumerate() {
+            let mut rates = pad(&entrant.name);
+            let mut intervals = pad("");
+            for record in self.records[index].iter().chain(Some(self.total(index)).iter()) {
+                match (record.win_rate(), record.confidence_interval()) {
+                    (Some(rate), Some((low, high))) => {
+                        rates.push_str(&pad(&format!("{:.0}%", rate * 100.0)));
+                        intervals.push_str(&pad(&format!("{:.0}-{:.0}%", low * 100.0,
+                                                         high * 100.0)));
+                    }
+                    _ => {
+                        rates.push_str(&pad("-"));
+                        intervals.push_str(&pad(""));
+                    }
+                }
+            }
+            result.push_str(&rates);
+            result.push('\n');
+            result.push_str(intervals.trim_end());
+            result.push('\n');
+        }
+        result
+    }
+}
+
+
+fn spawn_bot(entrant: &Entrant) -> io::Result<(Participant, JoinHandle<()>)> {
+    let (server_input, bot_output) = pipe();
+    let (bot_input, server_output) = pipe();
+    let strategy = entrant.strategy;
+    let name = entrant.name.clone();
+    
+    let handle = try!(thread::Builder::new().name(name.clone()).spawn(move|| {
+        strategy(bot_input, bot_output, name);
+    }));
+    Ok((Participant::new_boxed(server_input, server_output, entrant.name.clone()), handle))
+}
+
+// Returns how the first entrant did against the second. First rounds use seeds counting up from
+// first_round.
+pub fn play_match(a: &Entrant, b: &Entrant, config: &TournamentConfig, first_round: u32)
+        -> io::Result<Record> {
+    let mut record = Record::default();
+    
+    for round in 0..config.rounds_per_match {
+        // the order of spawning decides the order of turns
+        let order = if round % 2 == 0 { [a, b] } else { [b, a] };
+        let mut participants = vec![];
+        let mut handles = vec![];
+        for entrant in order.iter() {
+            let (mut participant, handle) = try!(spawn_bot(entrant));
+            try!(notify(&mut participant.output, Notification::NewRound,
+                        participant.protocol));
+            participants.push(participant);
+            handles.push(handle);
+        }
+        
+        let seed = config.seed.wrapping_add(first_round).wrapping_add(round);
+        let generator = next_map(&config.maps, round as usize, participants.len());
+        let replay = execute_round(&mut participants, &*generator, &config.rules,
+                                   Duration::from_millis(0), seed);
+        
+        // hanging up on the bots lets them finish
+        participants.clear();
+        for handle in handles {
+            if handle.join().is_err() {
+                println!("A bot panicked during round with seed {}.", seed);
+            }
+        }
+        
+        let first_places = replay.placements.iter().filter(|&&(_, place)| place == 1)
+                                            .map(|&(ref name, _)| name).collect::<Vec<_>>();
+        match first_places.len() {
+            1 if *first_places[0] == a.name => record.wins += 1,
+            1 if *first_places[0] == b.name => record.losses += 1,
+            _ => record.draws += 1,
+        }
+    }
+    
+    Ok(record)
+}
+
+
+// Every entrant plays every other entrant once.
+pub fn round_robin(entrants: &[Entrant], config: &TournamentConfig) -> io::Result<Standings> {
+    let mut standings = Standings::new(entrants);
+    let mut first_round = 0;
+    
+    for a in 0..entrants.len() {
+        for b in a + 1..entrants.len() {
+            let record = try!(play_match(&entrants[a], &entrants[b], config, first_round));
+            standings.record(a, b, record);
+            first_round += config.rounds_per_match;
+        }
+    }
+    Ok(standings)
+}
+
+// Entrants with similar scores play each other for a number of passes.
+pub fn swiss(entrants: &[Entrant], config: &TournamentConfig, passes: u32)
+        -> io::Result<Standings> {
+    let mut standings = Standings::new(entrants);
+    let mut first_round = 0;
+    
+    for _ in 0..passes {
+        let scores = (0..entrants.len()).map(|index| standings.total(index).score())
+                                        .collect::<Vec<_>>();
+        let played = standings.records.iter().map(|records| {
+            records.iter().map(|record| record.rounds() > 0).collect()
+        }).collect::<Vec<_>>();
+        
+        for (a, b) in swiss_pairings(&scores, &played) {
+            let record = try!(play_match(&entrants[a], &entrants[b], config, first_round));
+            standings.record(a, b, record);
+            first_round += config.rounds_per_match;
+        }
+    }
+    Ok(standings)
+}
+
+// Pairs each entrant, from the best score down, with the next best one it hasn't played yet, or
+// the next best one at all if it has played all of them. With an odd number of entrants, the
+// lowest one left over sits out.
+pub fn swiss_pairings(scores: &[f64], played: &[Vec<bool>]) -> Vec<(usize, usize)> {
+    let mut ranked = (0..scores.len()).collect::<Vec<_>>();
+    // stable so that earlier entrants win ties
+    ranked.sort_by(|a, b| scores[*b].partial_cmp(&scores[*a]).unwrap());
+    
+    let mut pairings = vec![];
+    while ranked.len() >= 2 {
+        let a = ranked.remove(0);
+        let index = ranked.iter().position(|b| !played[a][*b]).unwrap_or(0);
+        let b = ranked.remove(index);
+        pairings.push((a, b));
+    }
+    pairings
+}
+
+
+#[test]
+fn intervals() {
+    let (low, high) = wilson_interval(0.5, 10);
+    assert!((low - 0.237).abs() < 0.001);
+    assert!((high - 0.763).abs() < 0.001);
+    
+    let (low, high) = wilson_interval(1.0, 10);
+    assert!(low > 0.5 && low < 0.75);
+    assert_eq!(high, 1.0);
+    
+    assert_eq!(wilson_interval(0.0, 0), (0.0, 1.0));
+}
+
+#[test]
+fn pairing() {
+    let played = vec![vec![false; 5]; 5];
+    assert_eq!(swiss_pairings(&[1.0, 3.0, 0.0, 2.0, 2.0], &played), vec![(1, 3), (4, 0)]);
+    
+    let mut played = vec![vec![false; 4]; 4];
+    played[0][1] = true;
+    played[1][0] = true;
+    assert_eq!(swiss_pairings(&[2.0, 1.0, 1.0, 0.0], &played), vec![(0, 2), (1, 3)]);
+    
+    played[0][2] = true;
+    played[0][3] = true;
+    assert_eq!(swiss_pairings(&[2.0, 1.0, 1.0, 0.0], &played), vec![(0, 1), (2, 3)]);
+}
+
+#[test]
+fn tournament() {
+    let entrants = vec![
+        Entrant::new("hunter".to_string(), strategy("hunter").unwrap()),
+        Entrant::new("drifter".to_string(), strategy("drifter").unwrap()),
+        Entrant::new("drifter 2".to_string(), strategy("drifter").unwrap()),
+    ];
+    let mut config = TournamentConfig::new();
+    config.rounds_per_match = 2;
+    config.rules.tick_limit = Some(300);
+    
+    let standings = round_robin(&entrants, &config).unwrap();
+    for a in 0..entrants.len() {
+        assert_eq!(standings.records[a][a].rounds(), 0);
+        for b in 0..entrants.len() {
+            if a != b {
+                assert_eq!(standings.records[a][b].rounds(), 2);
+                assert_eq!(standings.records[a][b], standings.records[b][a].reversed());
+            }
+        }
+    }
+    assert_eq!(standings.matrix().lines().count(), 1 + entrants.len() * 2);
+}
@@ -2,7 +2,7 @@ use std::ops::{Add, Sub, Shr, Mul};
 use std::cmp::{max, min};
 
 
-#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
 pub struct Vector2<A> {
     pub x: A,
     pub y: A,
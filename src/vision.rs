@@ -0,0 +1,108 @@
+// What a bot is allowed to see when the round's rules limit vision.
+
+use area::*;
+use appearance::*;
+use space::*;
+
+
+impl Area {
+    pub fn appearance_seen_from(&self, viewer: Position, focus: Position) -> Appearance {
+        if self.visible(viewer, focus) {
+            self.appearance_at(focus)
+        }
+        else {
+            Appearance::Unknown
+        }
+    }
+    
+    pub fn visible(&self, viewer: Position, focus: Position) -> bool {
+        let dx = focus.x - viewer.x;
+        let dy = focus.y - viewer.y;
+        let distance = dx.abs().max(dy.abs());
+        
+        if let Some(range) = self.rules.view_range {
+            if distance > range as i32 {
+                return false;
+            }
+        }
+        if !self.rules.line_of_sight {
+            return true;
+        }
+        
+        // the squares in between are the ones nearest to a straight line between the centers
+        for step in 1..distance {
+            let between = Position::new(viewer.x + rounded_ratio(dx * step, distance),
+                                        viewer.y + rounded_ratio(dy * step, distance));
+            match self.appearance_at(between) {
+                Appearance::Bot | Appearance::Block => return false,
+                _ => (),
+            }
+        }
+        true
+    }
+}
+
+
+// Rounds halves away from zero so that lines are symmetrical about both axes.
+fn rounded_ratio(numerator: i32, denominator: i32) -> i32 {
+    let magnitude = (numerator.abs() * 2 + denominator) / (denominator * 2);
+    if numerator < 0 {
+        -magnitude
+    }
+    else {
+        magnitude
+    }
+}
+
+
+#[cfg(test)]
+use space::Direction::*;
+#[cfg(test)]
+use session::Rules;
+
+#[test]
+fn unlimited_by_default() {
+    let mut area = Area::new();
+    let origin = Position::default();
+    make_block(&mut area, origin + East);
+    
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 100), Appearance::Floor);
+}
+
+#[test]
+fn view_range() {
+    let mut area = Area::new();
+    area.rules = Rules {
+        view_range: Some(3),
+        ..Rules::new()
+    };
+    let origin = Position::default();
+    
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 3 + South * 3), Appearance::Floor);
+    assert_eq!(area.appearance_seen_from(origin, origin + West * 4), Appearance::Unknown);
+    assert_eq!(area.appearance_seen_from(origin, origin + North * 4 + East), Appearance::Unknown);
+}
+
+#[test]
+fn line_of_sight() {
+    let mut area = Area::new();
+    area.rules = Rules {
+        line_of_sight: true,
+        ..Rules::new()
+    };
+    let origin = Position::default();
+    make_block(&mut area, origin + East * 2);
+    make_bot(&mut area, origin + North * 2);
+    make_abyss(&mut area, origin + West);
+    
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 2), Appearance::Block);
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 3), Appearance::Unknown);
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 6 + North), Appearance::Unknown);
+    assert_eq!(area.appearance_seen_from(origin, origin + East * 6 + North * 3),
+               Appearance::Floor);
+    assert_eq!(area.appearance_seen_from(origin, origin + North * 5), Appearance::Unknown);
+    assert_eq!(area.appearance_seen_from(origin, origin + West * 5), Appearance::Floor);
+    
+    // blocked both ways
+    assert_eq!(area.appearance_seen_from(origin + East * 3, origin), Appearance::Unknown);
+}
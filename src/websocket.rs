@@ -0,0 +1,364 @@
+// Just enough of WebSockets (RFC 6455) for browsers to connect bots to the simulation port. The
+// opening handshake is an HTTP request that the server answers with a hash of the request's key,
+// and from then on each message travels in one or more frames. Frames from the browser are masked;
+// frames from the server are not.
+
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Longer requests and messages are taken for something other than a bot.
+const MAX_REQUEST_LENGTH: usize = 8192;
+pub const MAX_MESSAGE_LENGTH: usize = 65536;
+
+const OPCODE_CONTINUATION: u8 = 0;
+const OPCODE_TEXT: u8 = 1;
+const OPCODE_BINARY: u8 = 2;
+const OPCODE_CLOSE: u8 = 8;
+const OPCODE_PING: u8 = 9;
+const OPCODE_PONG: u8 = 10;
+
+pub const BAD_REQUEST: &'static str = "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
+
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for shift in (0..8).rev() {
+        message.push((bits >> (shift * 8)) as u8);
+    }
+    
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for index in 0..16 {
+            w[index] = (chunk[index * 4] as u32) << 24 | (chunk[index * 4 + 1] as u32) << 16
+                       | (chunk[index * 4 + 2] as u32) << 8 | chunk[index * 4 + 3] as u32;
+        }
+        for index in 16..80 {
+            w[index] = (w[index - 3] ^ w[index - 8] ^ w[index - 14] ^ w[index - 16]).rotate_left(1);
+        }
+        
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (index, word) in w.iter().enumerate() {
+            let (f, k) = match index {
+                0...19 => ((b & c) | (!b & d), 0x5A827999),
+                20...39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40...59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k)
+                        .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+    
+    let mut result = [0; 20];
+    for (index, word) in h.iter().enumerate() {
+        for byte in 0..4 {
+            result[index * 4 + byte] = (word >> (24 - byte * 8)) as u8;
+        }
+    }
+    result
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::new();
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+                   | *chunk.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                result.push(ALPHABET[(bits >> (18 - index * 6)) as usize & 63] as char);
+            }
+            else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+
+pub struct Request {
+    pub path: String,
+    // None if the request isn't asking for a WebSocket.
+    pub key: Option<String>,
+}
+
+// Reads the request up to and including the blank line that ends its headers.
+pub fn read_request(input: &mut Read) -> io::Result<Request> {
+    let mut head = vec![];
+    let mut buf = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Request too long."));
+        }
+        try!(input.read_exact(&mut buf));
+        head.push(buf[0]);
+    }
+    
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let path = match lines.next().map(|line| line.split(' ').collect::<Vec<_>>()) {
+        Some(ref words) if words.len() == 3 && words[0] == "GET" => words[1].to_string(),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a GET request.")),
+    };
+    let mut key = None;
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+    
+    Ok(Request {
+        path: path,
+        key: key,
+    })
+}
+
+pub fn accept_response(key: &str) -> String {
+    format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n", accept_key(key))
+}
+
+// The value of the name parameter in a path like "/?name=Hunter%20Bot", if there is one.
+pub fn name_parameter(path: &str) -> Option<String> {
+    let query = match path.find('?') {
+        None => return None,
+        Some(index) => &path[index + 1..],
+    };
+    for parameter in query.split('&') {
+        let mut parts = parameter.splitn(2, '=');
+        if let (Some("name"), Some(value)) = (parts.next(), parts.next()) {
+            return Some(percent_decode(value));
+        }
+    }
+    None
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut result = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = match bytes[index] {
+            b'%' => text.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match (escaped, bytes[index]) {
+            (Some(byte), _) => {
+                result.push(byte);
+                index += 3;
+            }
+            (None, b'+') => {
+                result.push(b' ');
+                index += 1;
+            }
+            (None, byte) => {
+                result.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+
+// A frame from the server, which is never split up or masked.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0...125 => result.push(length as u8),
+        length @ 126...65535 => {
+            result.push(126);
+            result.push((length >> 8) as u8);
+            result.push(length as u8);
+        }
+        length => {
+            result.push(127);
+            for shift in (0..8).rev() {
+                result.push((length as u64 >> (shift * 8)) as u8);
+            }
+        }
+    }
+    result.extend_from_slice(payload);
+    result
+}
+
+pub fn text_frame(payload: &[u8]) -> Vec<u8> {
+    encode_frame(OPCODE_TEXT, payload)
+}
+
+pub fn close_frame() -> Vec<u8> {
+    encode_frame(OPCODE_CLOSE, &[])
+}
+
+// Returns whether this is the last frame of its message, its opcode and its unmasked payload.
+fn read_frame(input: &mut Read) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    try!(input.read_exact(&mut header));
+    let last = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut extended = [0; 2];
+            try!(input.read_exact(&mut extended));
+            (extended[0] as usize) << 8 | extended[1] as usize
+        }
+        127 => {
+            let mut extended = [0; 8];
+            try!(input.read_exact(&mut extended));
+            extended.iter().fold(0u64, |length, byte| length << 8 | *byte as u64) as usize
+        }
+        length => length as usize,
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too long."));
+    }
+    
+    let mut mask = [0; 4];
+    if masked {
+        try!(input.read_exact(&mut mask));
+    }
+    let mut payload = vec![0; length];
+    try!(input.read_exact(&mut payload));
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+    Ok((last, opcode, payload))
+}
+
+// Passes the contents of each message to the forward stream on a line of its own, answering pings
+// and closing when the browser does. Newlines within messages become spaces, which is enough to
+// keep JSON messages apart. Returns once either side hangs up.
+pub fn forward_messages<W: Write>(input: &mut Read, replies: &Mutex<W>, forward: &mut Write)
+        -> io::Result<()> {
+    let reply = |frame: Vec<u8>| match replies.lock() {
+        Err(_) => Err(io::Error::new(io::ErrorKind::Other, "Lock poisoned.")),
+        Ok(mut output) => output.write_all(&frame),
+    };
+    
+    let mut message = vec![];
+    loop {
+        let (last, opcode, payload) = try!(read_frame(input));
+        match opcode {
+            OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                if message.len() + payload.len() > MAX_MESSAGE_LENGTH {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too long."));
+                }
+                message.extend(payload);
+                if last {
+                    for byte in message.iter_mut() {
+                        if *byte == b'\n' || *byte == b'\r' {
+                            *byte = b' ';
+                        }
+                    }
+                    message.push(b'\n');
+                    try!(forward.write_all(&message));
+                    message.clear();
+                }
+            }
+            OPCODE_PING => try!(reply(encode_frame(OPCODE_PONG, &payload))),
+            OPCODE_PONG => (),
+            OPCODE_CLOSE => {
+                // the browser may have gone already
+                drop(reply(close_frame()));
+                return Ok(());
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown opcode.")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+use std::io::Cursor;
+
+#[cfg(test)]
+fn masked_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1, 2, 3, 4];
+    let mut result = vec![first, 0x80 | payload.len() as u8];
+    result.extend_from_slice(&mask);
+    result.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+    result
+}
+
+#[test]
+fn hashing() {
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(hex(&sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    
+    // the example from RFC 6455
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn requests() {
+    let request = "GET /?name=Browser%20Bot&x=1 HTTP/1.1\r\nHost: localhost\r\n\
+                   sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\nleft over";
+    let mut input = Cursor::new(request.as_bytes().to_vec());
+    let request = read_request(&mut input).unwrap();
+    assert_eq!(request.key, Some("dGhlIHNhbXBsZSBub25jZQ==".to_string()));
+    assert_eq!(name_parameter(&request.path), Some("Browser Bot".to_string()));
+    assert_eq!(input.position(), 103);
+    
+    assert_eq!(name_parameter("/"), None);
+    assert_eq!(name_parameter("/?name=a+b%2"), Some("a b%2".to_string()));
+    
+    let mut input = Cursor::new(b"POST / HTTP/1.1\r\n\r\n".to_vec());
+    assert!(read_request(&mut input).is_err());
+}
+
+#[test]
+fn framing() {
+    assert_eq!(text_frame(b"hi"), vec![0x81, 2, b'h', b'i']);
+    assert_eq!(text_frame(&[0; 200])[..4], [0x81, 126, 0, 200]);
+    
+    let mut input = masked_frame(0x01, b"{\"command\":");
+    input.extend(masked_frame(0x89, b"?"));
+    input.extend(masked_frame(0x80, b"\n\"wait\"}"));
+    input.extend(masked_frame(0x82, &[1, 2]));
+    input.extend(masked_frame(0x88, &[]));
+    input.extend(masked_frame(0x81, b"never read"));
+    
+    let replies = Mutex::new(vec![]);
+    let mut forward = vec![];
+    forward_messages(&mut Cursor::new(input), &replies, &mut forward).unwrap();
+    assert_eq!(forward, b"{\"command\": \"wait\"}\n\x01\x02\n".to_vec());
+    assert_eq!(*replies.lock().unwrap(), vec![0x8A, 1, b'?', 0x88, 0]);
+}